@@ -3,9 +3,18 @@
 #ifndef KEYPAD_H
 #define KEYPAD_H
 
+#include <functional>
+#include <string>
+
 #include "ckeypad"
 
 typedef void (*key_event_handler) (char, uint32_t);
+typedef void (*key_event_details_handler) (const KpEvent *, uint32_t);
+typedef void (*recovery_event_handler) (RecoveryEvent, uint32_t);
+typedef void (*lock_event_handler) (Lock, uint32_t);
+typedef void (*rejected_key_handler) (char, Lock, uint32_t);
+typedef void (*chord_handler) (const char *, uint32_t);
+typedef void (*text_event_handler) (TextEventKind, char, uint32_t);
 
 class keypad {
 public:
@@ -13,13 +22,20 @@ public:
   enum locking_mode {
       UNLOCKED  = (int)Lock::Unlocked,           // All keys unlocked
       LOCKED    = (int)Lock::Locked,             // All keys locked
-      ON_OFF    = (int)Lock::UnlockedPowerOnly   // All keys locked except for the power key
+      ON_OFF    = (int)Lock::UnlockedPowerOnly,  // All keys locked except for the power key
+      CUSTOM    = (int)Lock::Custom              // All keys locked except for the ones set with SetAllowedKeys
   };
 
   static int          Initialize                (int)                        // Initializes the keypad. Legacy int argument is unused.
   {
       kp = ::keypad_new();
-      return !::keypad_init(kp);
+      return (int)::keypad_init(kp);
+  }
+
+  static int          Initialize                (const char *path, uint16_t addr)  // Initializes the keypad on the given I2C bus and chip address.
+  {
+      kp = ::keypad_new();
+      return (int)::keypad_init_ex(kp, path, addr);
   }
 
   static void         Run                       ()                           // Runs the main loop which addresses the mux pins and listens on the read pins.
@@ -27,9 +43,38 @@ public:
       ::keypad_run(kp);
   }
 
+  static int          SetInterrupt              (const char *chip, uint32_t line)  // Sleeps until the MCP23017 INTA signal on the given GPIO line while no key is pressed. Returns a non-zero KpError on error.
+  {
+      return (int)::keypad_set_interrupt(kp, chip, line);
+  }
+
+  static int          Start                     ()                           // Starts the main loop in a background thread. Returns a non-zero KpError on error.
+  {
+      return (int)::keypad_start(kp);
+  }
+
+  static int          Join                      ()                           // Waits for the background thread to exit. Returns a non-zero KpError if it failed.
+  {
+      return (int)::keypad_join(kp);
+  }
+
   static locking_mode GetLock                   ()                           // Returns the current locking status.
   {
-      return (locking_mode)::keypad_get_lock(kp);
+      Lock lock = Lock::Locked;
+      ::keypad_get_lock(kp, &lock);
+      return (locking_mode)lock;
+  }
+
+  static std::string  GetPressedKeys            ()                           // Returns the keys held down, in the order they were pressed.
+  {
+      char buf[64] = "";
+      ::keypad_get_pressed(kp, buf, sizeof buf);
+      return buf;
+  }
+
+  static bool         IsPressed                 (char key)                   // Checks if the key is held down.
+  {
+      return GetPressedKeys().find(key) != std::string::npos;
   }
 
   static void         SetLock                   (locking_mode mode)          // Locks or unlocks the keypad.
@@ -37,6 +82,103 @@ public:
       ::keypad_set_lock(kp, static_cast<Lock>(mode));
   }
 
+  static void         SetAllowedKeys            (const char *keys)           // Locks all keys except the given ones.
+  {
+      ::keypad_set_allowed(kp, keys);
+  }
+
+  static int          AddChord                  (const char *name, const char *keys, uint32_t hold_ms = 0, bool suppress = false)  // Registers a key combination reported to the chord handler once held for hold_ms. Returns a non-zero KpError on error.
+  {
+      return (int)::keypad_add_chord(kp, name, keys, hold_ms, suppress);
+  }
+
+  static int          RemoveChord               (const char *name)           // Unregisters a key combination. Returns a non-zero KpError on error.
+  {
+      return (int)::keypad_remove_chord(kp, name);
+  }
+
+  static int          EnableTextEntry           (const char *path = nullptr) // Enables multi-tap text entry, path of the key assignment file or nullptr for the phone layout. Returns a non-zero KpError on error.
+  {
+      return (int)::keypad_enable_text_entry(kp, path);
+  }
+
+  static void         DisableTextEntry          ()                           // Disables multi-tap text entry.
+  {
+      ::keypad_disable_text_entry(kp);
+  }
+
+  static std::string  GetText                   ()                           // Returns the committed text.
+  {
+      char buf[256] = "";
+      ::keypad_get_text(kp, buf, sizeof buf);
+      return buf;
+  }
+
+  static void         ClearText                 ()                           // Discards the text and the character under composition.
+  {
+      ::keypad_clear_text(kp);
+  }
+
+  static void         SetAutoLock               (uint32_t timeout_ms, locking_mode mode = LOCKED)  // Locks the keypad after the given time without key presses. Zero disables auto-lock.
+  {
+      ::keypad_set_auto_lock(kp, timeout_ms, static_cast<Lock>(mode));
+  }
+
+  static void         SetUnlockSequence         (const char *keys)           // Sets the keys to type to unlock the keypad. Empty string disables.
+  {
+      ::keypad_set_unlock_sequence(kp, keys);
+  }
+
+  static void         SetDebounce               (uint32_t press, uint32_t release)  // Sets the number of stable scan samples required to register a press or a release.
+  {
+      ::keypad_set_debounce(kp, press, release);
+  }
+
+  static void         SetRepeat                 (uint32_t delay_ms, uint32_t interval_ms)  // Sets auto-repeat timing for keys marked as repeating. Zero delay disables auto-repeat.
+  {
+      ::keypad_set_repeat(kp, delay_ms, interval_ms);
+  }
+
+  static void         SetLongPress              (char key, uint32_t ms)      // Sets the long press threshold of a key. Zero disables long press detection.
+  {
+      ::keypad_set_long_press(kp, key, ms);
+  }
+
+  static int          EnableUinput              (const char *name, const char *keycodes = nullptr)  // Makes the keypad appear as a Linux input device. Returns a non-zero KpError on error.
+  {
+      return (int)::keypad_set_uinput(kp, name, keycodes);
+  }
+
+  static void         SetRecovery               (uint32_t retries, uint32_t delay_ms)  // Sets how many times to try recovering from an I2C error before giving up. Zero fails on the first error.
+  {
+      ::keypad_set_recovery(kp, retries, delay_ms);
+  }
+
+  static int          LoadKeymap                (const char *path)           // Loads key assignment from a file. Returns a non-zero KpError on error.
+  {
+      return (int)::keypad_load_keymap(kp, path);
+  }
+
+  static int          LoadWiring                (const char *path)           // Loads connection of the key matrix to the chip from a file. Returns a non-zero KpError on error.
+  {
+      return (int)::keypad_load_wiring(kp, path);
+  }
+
+  static bool         PollEvent                 (KpEvent *event)             // Fetches a queued key event without blocking. Returns false if there is none.
+  {
+      return ::keypad_poll_event(kp, event) == KpError::Ok;
+  }
+
+  static bool         WaitEvent                 (KpEvent *event, int timeout_ms = -1)  // Waits for a key event. Negative timeout waits forever. Returns false on timeout.
+  {
+      return ::keypad_wait_event(kp, event, timeout_ms) == KpError::Ok;
+  }
+
+  static const char  *LastErrorMessage          ()                           // Returns the message of the last failed call on this thread.
+  {
+      return ::keypad_last_error_message();
+  }
+
   static void         Terminate                 ()                           // Terminates the keypad driver.
   {
       ::keypad_delete(kp);
@@ -51,8 +193,99 @@ public:
   {
       ::keypad_set_on_released(kp, handler, 0);
   }
+  static void         SetKeyLongPressEventHandler (key_event_handler handler)  // Sets the handler for key long press events.
+  {
+      ::keypad_set_on_long_press(kp, handler, 0);
+  }
+  static void         SetKeyRepeatEventHandler  (key_event_handler handler)  // Sets the handler for key auto-repeat events.
+  {
+      ::keypad_set_on_repeat(kp, handler, 0);
+  }
+  static void         SetKeyEventHandler        (key_event_details_handler handler)  // Sets the handler receiving every key event with its time and matrix position.
+  {
+      ::keypad_set_on_event(kp, handler, 0);
+  }
+  static void         SetRecoveryEventHandler   (recovery_event_handler handler)  // Sets the handler for I2C error recovery events.
+  {
+      ::keypad_set_on_recovery(kp, handler, 0);
+  }
+  static void         SetLockChangedEventHandler (lock_event_handler handler)  // Sets the handler for lock status changes.
+  {
+      ::keypad_set_on_lock_changed(kp, handler, 0);
+  }
+  static void         SetKeyRejectedEventHandler (rejected_key_handler handler)  // Sets the handler for keys pressed while the keypad is locked.
+  {
+      ::keypad_set_on_rejected(kp, handler, 0);
+  }
+  static void         SetChordEventHandler      (chord_handler handler)      // Sets the handler for key combinations registered with AddChord.
+  {
+      ::keypad_set_on_chord(kp, handler, 0);
+  }
+  static void         SetTextEventHandler       (text_event_handler handler) // Sets the handler for composing and committed characters of text entry.
+  {
+      ::keypad_set_on_text(kp, handler, 0);
+  }
+
+  // Handlers below accept any callable, e.g. a lambda capturing `this` or the result of std::bind.
+  static void         SetKeyPressEventHandler   (std::function<void(char)> handler)
+  {
+      ::keypad_set_on_pressed_ex(kp, call<char>, wrap(std::move(handler)), destroy<std::function<void(char)>>);
+  }
+  static void         SetKeyReleaseEventHandler (std::function<void(char)> handler)
+  {
+      ::keypad_set_on_released_ex(kp, call<char>, wrap(std::move(handler)), destroy<std::function<void(char)>>);
+  }
+  static void         SetKeyLongPressEventHandler (std::function<void(char)> handler)
+  {
+      ::keypad_set_on_long_press_ex(kp, call<char>, wrap(std::move(handler)), destroy<std::function<void(char)>>);
+  }
+  static void         SetKeyRepeatEventHandler  (std::function<void(char)> handler)
+  {
+      ::keypad_set_on_repeat_ex(kp, call<char>, wrap(std::move(handler)), destroy<std::function<void(char)>>);
+  }
+  static void         SetKeyEventHandler        (std::function<void(const KpEvent *)> handler)
+  {
+      ::keypad_set_on_event_ex(kp, call<const KpEvent *>, wrap(std::move(handler)), destroy<std::function<void(const KpEvent *)>>);
+  }
+  static void         SetRecoveryEventHandler   (std::function<void(RecoveryEvent)> handler)
+  {
+      ::keypad_set_on_recovery_ex(kp, call<RecoveryEvent>, wrap(std::move(handler)), destroy<std::function<void(RecoveryEvent)>>);
+  }
+  static void         SetLockChangedEventHandler (std::function<void(Lock)> handler)
+  {
+      ::keypad_set_on_lock_changed_ex(kp, call<Lock>, wrap(std::move(handler)), destroy<std::function<void(Lock)>>);
+  }
+  static void         SetKeyRejectedEventHandler (std::function<void(char, Lock)> handler)
+  {
+      ::keypad_set_on_rejected_ex(kp, call<char, Lock>, wrap(std::move(handler)), destroy<std::function<void(char, Lock)>>);
+  }
+  static void         SetChordEventHandler      (std::function<void(const char *)> handler)
+  {
+      ::keypad_set_on_chord_ex(kp, call<const char *>, wrap(std::move(handler)), destroy<std::function<void(const char *)>>);
+  }
+  static void         SetTextEventHandler       (std::function<void(TextEventKind, char)> handler)
+  {
+      ::keypad_set_on_text_ex(kp, call<TextEventKind, char>, wrap(std::move(handler)), destroy<std::function<void(TextEventKind, char)>>);
+  }
 private:
   static struct Keypad *kp;
+
+  // Moves the handler to the heap, it is deleted by the library through `destroy`.
+  template <typename... Args>
+  static void        *wrap                      (std::function<void(Args...)> handler)
+  {
+      return new std::function<void(Args...)>(std::move(handler));
+  }
+  template <typename... Args>
+  static void         call                      (Args... args, void *user_data)
+  {
+      (*static_cast<std::function<void(Args...)> *>(user_data))(args...);
+  }
+  template <typename F>
+  static void         destroy                   (void *user_data)
+  {
+      delete static_cast<F *>(user_data);
+  }
 };
 
 #endif
@@ -0,0 +1,194 @@
+//! Keypad wiring diagnostics.
+
+use anyhow::{Context, Error, bail};
+use std::{
+    env,
+    process::ExitCode,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use keypad::{keypad::Keypad, layout::Keymap};
+
+const USAGE: &str = "\
+Usage: keypad-diag [options] <command>
+
+Commands:
+  live                  print every key state change
+  check                 look for stuck keys and shorts between rows (release all keys first)
+  guided                press every key once, report keys never seen
+
+Options:
+  --device PATH         I2C bus device (default /dev/i2c-1)
+  --address ADDR        chip address, decimal or 0x hexadecimal (default 0x20)
+  --keymap PATH         key assignment file
+  --wiring PATH         connection of the key matrix to the chip
+  --timeout SECS        time limit of the guided test (default 60)
+  --help                show this message";
+
+/// Interval between raw scans.
+const PERIOD: Duration = Duration::from_millis(20);
+/// Number of scans a key must read pressed in to be reported as stuck.
+const STUCK_SCANS: usize = 10;
+
+type Matrix = [[[bool; 3]; 4]; 2];
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(2),
+        Err(e) => {
+            eprintln!("keypad-diag: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Returns `false` if a problem was found.
+fn run() -> Result<bool, Error> {
+    let mut builder = Keypad::builder();
+    let mut keymap = Keymap::default();
+    let mut timeout = Duration::from_secs(60);
+    let mut command = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("{arg} requires a value"))
+        };
+        match arg.as_str() {
+            "--device" | "--address" | "--wiring" => builder = builder.option(&arg, &value()?)?,
+            "--keymap" => keymap = Keymap::load(value()?).context("cannot load keymap")?,
+            "--timeout" => {
+                let secs = value()?.parse().context("invalid timeout")?;
+                timeout = Duration::from_secs(secs);
+            }
+            "--help" => {
+                println!("{USAGE}");
+                return Ok(true);
+            }
+            "live" | "check" | "guided" if command.is_none() => command = Some(arg),
+            _ => bail!("unexpected argument `{arg}`\n{USAGE}"),
+        }
+    }
+    let Some(command) = command else {
+        bail!("no command given\n{USAGE}");
+    };
+
+    let keypad = builder.open().context("cannot open keypad")?;
+    match command.as_str() {
+        "live" => live(&keypad, &keymap),
+        "check" => check(&keypad, &keymap),
+        _ => guided(&keypad, &keymap, timeout),
+    }
+}
+
+fn live(keypad: &Keypad, keymap: &Keymap) -> Result<bool, Error> {
+    println!("Press keys, Ctrl+C to exit.");
+    let mut last = Matrix::default();
+    loop {
+        let matrix = keypad.raw_scan()?;
+        for (pad, row, column) in positions() {
+            let pressed = matrix[pad][row][column];
+            if pressed != last[pad][row][column] {
+                let state = if pressed { "pressed" } else { "released" };
+                println!("{}  {state}", describe(keymap, pad, row, column));
+            }
+        }
+        last = matrix;
+        sleep(PERIOD);
+    }
+}
+
+fn check(keypad: &Keypad, keymap: &Keymap) -> Result<bool, Error> {
+    let mut ok = true;
+
+    let mut count = [[[0; 3]; 4]; 2];
+    for _ in 0..STUCK_SCANS {
+        let matrix = keypad.raw_scan()?;
+        for (pad, row, column) in positions() {
+            if matrix[pad][row][column] {
+                count[pad][row][column] += 1;
+            }
+        }
+        sleep(PERIOD);
+    }
+    for (pad, row, column) in positions() {
+        match count[pad][row][column] {
+            0 => (),
+            STUCK_SCANS => {
+                println!("stuck:    {}", describe(keymap, pad, row, column));
+                ok = false;
+            }
+            n => {
+                println!(
+                    "unstable: {} ({n} of {STUCK_SCANS} scans)",
+                    describe(keymap, pad, row, column)
+                );
+                ok = false;
+            }
+        }
+    }
+
+    let rows = keypad.check_rows()?;
+    for (pad, row) in &rows.grounded {
+        println!("grounded: pad {pad} row {row}");
+        ok = false;
+    }
+    for ((pad1, row1), (pad2, row2)) in &rows.shorts {
+        println!("short:    pad {pad1} row {row1} - pad {pad2} row {row2}");
+        ok = false;
+    }
+
+    if ok {
+        println!("No problems found.");
+    }
+    Ok(ok)
+}
+
+fn guided(keypad: &Keypad, keymap: &Keymap, timeout: Duration) -> Result<bool, Error> {
+    let total = positions().count();
+    println!(
+        "Press every key once ({total} keys, {} s).",
+        timeout.as_secs()
+    );
+
+    let mut seen = Matrix::default();
+    let mut remaining = total;
+    let start = Instant::now();
+    while remaining > 0 && start.elapsed() < timeout {
+        let matrix = keypad.raw_scan()?;
+        for (pad, row, column) in positions() {
+            if matrix[pad][row][column] && !seen[pad][row][column] {
+                seen[pad][row][column] = true;
+                remaining -= 1;
+                println!(
+                    "{}  ok, {remaining} left",
+                    describe(keymap, pad, row, column)
+                );
+            }
+        }
+        sleep(PERIOD);
+    }
+
+    for (pad, row, column) in positions() {
+        if !seen[pad][row][column] {
+            println!("never seen: {}", describe(keymap, pad, row, column));
+        }
+    }
+    if remaining == 0 {
+        println!("All keys work.");
+    }
+    Ok(remaining == 0)
+}
+
+/// All matrix positions as `(pad, row, column)`.
+fn positions() -> impl Iterator<Item = (usize, usize, usize)> {
+    (0..2).flat_map(|pad| (0..4).flat_map(move |row| (0..3).map(move |column| (pad, row, column))))
+}
+
+fn describe(keymap: &Keymap, pad: usize, row: usize, column: usize) -> String {
+    let symbol = keymap.translate(pad, row, column).symbol.chr() as char;
+    format!("pad {pad} row {row} column {column} `{symbol}`")
+}
@@ -0,0 +1,89 @@
+//! Keypad daemon: scans the keypad and serves its events over a Unix socket.
+
+use anyhow::{Context, Error, bail};
+use std::{
+    env,
+    process::{self, ExitCode},
+    sync::Arc,
+    thread,
+};
+
+use keypad::{
+    daemon::{SOCKET, Server},
+    interrupt::GpioInterrupt,
+    keypad::Keypad,
+    layout::Keymap,
+};
+
+const USAGE: &str = "\
+Usage: keypadd [options]
+
+Options:
+  --device PATH         I2C bus device (default /dev/i2c-1)
+  --address ADDR        chip address, decimal or 0x hexadecimal (default 0x20)
+  --socket PATH         socket to listen on (default /run/keypad.sock)
+  --keymap PATH         key assignment file
+  --wiring PATH         connection of the key matrix to the chip
+  --interrupt CHIP:LINE GPIO line connected to INTA, e.g. /dev/gpiochip0:17
+  --help                show this message";
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("keypadd: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let mut builder = Keypad::builder();
+    let mut socket = SOCKET.to_owned();
+    let mut keymap = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("{arg} requires a value"))
+        };
+        match arg.as_str() {
+            "--device" | "--address" | "--wiring" => builder = builder.option(&arg, &value()?)?,
+            "--socket" => socket = value()?,
+            "--keymap" => keymap = Some(Keymap::load(value()?).context("cannot load keymap")?),
+            "--interrupt" => {
+                let value = value()?;
+                let Some((chip, line)) = value.rsplit_once(':') else {
+                    bail!("--interrupt expects CHIP:LINE");
+                };
+                let irq = GpioInterrupt::new(chip, line.parse().context("invalid GPIO line")?)
+                    .context("cannot request interrupt line")?;
+                builder = builder.interrupt(irq);
+            }
+            "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => bail!("unknown option `{arg}`\n{USAGE}"),
+        }
+    }
+
+    let keypad = Arc::new(builder.open().context("cannot open keypad")?);
+    if let Some(keymap) = keymap {
+        keypad.set_keymap(keymap);
+    }
+    let server = Server::bind(&socket, keypad.clone())
+        .with_context(|| format!("cannot listen on {socket}"))?;
+    let scanner = keypad.spawn().context("cannot start scanning")?;
+    thread::Builder::new()
+        .name("keypad-server".into())
+        .spawn(move || {
+            if let Err(e) = server.run() {
+                eprintln!("keypadd: {e:#}");
+                process::exit(1);
+            }
+        })?;
+    // Scanning runs until the keypad fails for good.
+    scanner.join()
+}
@@ -0,0 +1,305 @@
+//! Key combinations, e.g. holding `*` `#` `0` to enter a service menu.
+
+use std::time::{Duration, Instant};
+
+use super::{
+    event::{KeyEvent, KeyEventKind},
+    layout::{KeyMask, Symbol},
+};
+
+/// Combination of keys held down together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord {
+    /// Name passed to the `OnChord` callback.
+    pub name: String,
+    pub keys: Vec<Symbol>,
+    /// Time all keys must be held before the chord fires.
+    pub hold: Duration,
+    /// Hide events of the keys from callbacks, the event queue and outputs.
+    ///
+    /// `Pressed` events of the keys are delayed while the keys held down
+    /// could still become the chord. They are delivered late if another key
+    /// is pressed, or a key is released or long pressed first. Once the whole
+    /// chord is held, the delayed events are dropped together with all further
+    /// events of their keys, even if the chord is released before `hold`
+    /// expires. Keys whose `Pressed` event was already delivered are not
+    /// affected.
+    pub suppress: bool,
+}
+
+#[derive(Debug)]
+struct Entry {
+    chord: Chord,
+    mask: KeyMask,
+    /// Time all keys were held down.
+    since: Option<Instant>,
+    fired: bool,
+}
+
+/// Chord detection state of the scanner.
+#[derive(Debug, Default)]
+pub(crate) struct Chords {
+    entries: Vec<Entry>,
+    /// Delayed `Pressed` events.
+    pending: Vec<KeyEvent>,
+    /// Keys whose events are dropped until released.
+    suppressed: KeyMask,
+}
+
+impl Chords {
+    /// Register a chord, replacing one with the same name.
+    pub fn insert(&mut self, chord: Chord) {
+        self.remove(&chord.name);
+        let mask = chord.keys.iter().copied().collect();
+        self.entries.push(Entry {
+            chord,
+            mask,
+            since: None,
+            fired: false,
+        });
+    }
+
+    /// Unregister a chord. Returns `false` if there is none with the name.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|e| e.chord.name != name);
+        self.entries.len() != len
+    }
+
+    /// Forget all held keys.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.suppressed = KeyMask::empty();
+        for entry in &mut self.entries {
+            entry.since = None;
+            entry.fired = false;
+        }
+    }
+
+    /// Process a key event, `held` are the keys held down after it.
+    /// Returns the events to deliver.
+    pub fn event(&mut self, event: KeyEvent, held: KeyMask) -> Vec<KeyEvent> {
+        let chr = event.symbol;
+        match event.kind {
+            KeyEventKind::Pressed => {
+                let mut completed = false;
+                for entry in &mut self.entries {
+                    if entry.since.is_none() && held.contains_all(&entry.mask) {
+                        entry.since = Some(event.timestamp);
+                        if entry.chord.suppress {
+                            for &key in &entry.chord.keys {
+                                if key == chr || self.pending.iter().any(|ev| ev.symbol == key) {
+                                    self.suppressed.insert(key);
+                                }
+                            }
+                            completed = true;
+                        }
+                    }
+                }
+                if completed {
+                    let suppressed = self.suppressed;
+                    self.pending.retain(|ev| !suppressed.contains(ev.symbol));
+                    if suppressed.contains(chr) {
+                        return self.pending.drain(..).collect();
+                    }
+                }
+
+                // Wait and see if the keys held down become a suppressed chord.
+                let partial = self
+                    .entries
+                    .iter()
+                    .any(|e| e.chord.suppress && e.since.is_none() && e.mask.contains_all(&held));
+                if partial {
+                    self.pending.push(event);
+                    return Vec::new();
+                }
+                self.flush(event)
+            }
+            KeyEventKind::Released => {
+                for entry in &mut self.entries {
+                    if entry.mask.contains(chr) {
+                        entry.since = None;
+                        entry.fired = false;
+                    }
+                }
+                if self.suppressed.contains(chr) {
+                    self.suppressed.remove(chr);
+                    return Vec::new();
+                }
+                self.flush(event)
+            }
+            KeyEventKind::LongPress | KeyEventKind::Repeat => {
+                if self.suppressed.contains(chr) {
+                    return Vec::new();
+                }
+                // A key held this long is used on its own.
+                self.flush(event)
+            }
+        }
+    }
+
+    /// Get names of chords held for their time at `now`, each once per hold.
+    pub fn poll(&mut self, now: Instant) -> Vec<String> {
+        let mut fired = Vec::new();
+        for entry in &mut self.entries {
+            if let Some(since) = entry.since
+                && !entry.fired
+                && now >= since + entry.chord.hold
+            {
+                entry.fired = true;
+                fired.push(entry.chord.name.clone());
+            }
+        }
+        fired
+    }
+
+    /// Delayed events followed by `event`.
+    fn flush(&mut self, event: KeyEvent) -> Vec<KeyEvent> {
+        let mut events: Vec<KeyEvent> = self.pending.drain(..).collect();
+        events.push(event);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use KeyEventKind::*;
+
+    /// Chord detection fed the way the scanner does it.
+    struct Keys {
+        chords: Chords,
+        held: KeyMask,
+        start: Instant,
+    }
+
+    impl Keys {
+        /// `*` `#` chord held for 100 ms, suppressed, and `A` `B` chord, not.
+        fn new() -> Self {
+            let mut chords = Chords::default();
+            for (name, keys, hold, suppress) in
+                [("service", b"*#", 100, true), ("ab", b"AB", 0, false)]
+            {
+                chords.insert(Chord {
+                    name: name.into(),
+                    keys: keys.iter().copied().map(Symbol::new).collect(),
+                    hold: Duration::from_millis(hold),
+                    suppress,
+                });
+            }
+            Self {
+                chords,
+                held: KeyMask::empty(),
+                start: Instant::now(),
+            }
+        }
+
+        fn at(&self, ms: u64) -> Instant {
+            self.start + Duration::from_millis(ms)
+        }
+
+        /// Process event of key `chr` at `ms`, get the events delivered.
+        fn event(&mut self, kind: KeyEventKind, chr: u8, ms: u64) -> Vec<(KeyEventKind, char)> {
+            let symbol = Symbol::new(chr);
+            match kind {
+                Pressed => self.held.insert(symbol),
+                Released => self.held.remove(symbol),
+                _ => (),
+            }
+            let event = KeyEvent {
+                symbol,
+                kind,
+                timestamp: self.at(ms),
+                pad: 0,
+                row: 0,
+                column: 0,
+            };
+            self.chords
+                .event(event, self.held)
+                .iter()
+                .map(|ev| (ev.kind, ev.symbol.chr() as char))
+                .collect()
+        }
+
+        fn poll(&mut self, ms: u64) -> Vec<String> {
+            self.chords.poll(self.at(ms))
+        }
+    }
+
+    #[test]
+    fn chord_key_alone_is_delivered_late() {
+        let mut keys = Keys::new();
+        assert_eq!(keys.event(Pressed, b'*', 0), []);
+        assert_eq!(
+            keys.event(Released, b'*', 50),
+            [(Pressed, '*'), (Released, '*')]
+        );
+
+        assert_eq!(keys.event(Pressed, b'#', 100), []);
+        assert_eq!(
+            keys.event(Pressed, b'1', 150),
+            [(Pressed, '#'), (Pressed, '1')]
+        );
+        assert_eq!(keys.event(Released, b'#', 200), [(Released, '#')]);
+        assert!(keys.poll(1000).is_empty());
+    }
+
+    #[test]
+    fn chord_key_can_be_long_pressed() {
+        let mut keys = Keys::new();
+        assert_eq!(keys.event(Pressed, b'*', 0), []);
+        assert_eq!(
+            keys.event(LongPress, b'*', 500),
+            [(Pressed, '*'), (LongPress, '*')]
+        );
+        assert_eq!(keys.event(Repeat, b'*', 600), [(Repeat, '*')]);
+
+        // The chord still fires, only the key not delivered yet is hidden.
+        assert_eq!(keys.event(Pressed, b'#', 700), []);
+        assert_eq!(keys.poll(800), ["service"]);
+        assert_eq!(keys.event(Released, b'#', 900), []);
+        assert_eq!(keys.event(Released, b'*', 900), [(Released, '*')]);
+    }
+
+    #[test]
+    fn suppressed_chord_hides_its_keys() {
+        let mut keys = Keys::new();
+        assert_eq!(keys.event(Pressed, b'*', 0), []);
+        assert_eq!(keys.event(Pressed, b'#', 10), []);
+        assert!(keys.poll(50).is_empty());
+        assert_eq!(keys.event(LongPress, b'*', 100), []);
+        assert_eq!(keys.poll(110), ["service"]);
+        assert!(keys.poll(200).is_empty());
+        assert_eq!(keys.event(Repeat, b'#', 200), []);
+        assert_eq!(keys.event(Released, b'*', 300), []);
+        assert_eq!(keys.event(Released, b'#', 300), []);
+
+        // Released before the hold time, events are hidden all the same.
+        assert_eq!(keys.event(Pressed, b'#', 400), []);
+        assert_eq!(keys.event(Pressed, b'*', 410), []);
+        assert_eq!(keys.event(Released, b'*', 420), []);
+        assert_eq!(keys.event(Released, b'#', 420), []);
+        assert!(keys.poll(1000).is_empty());
+
+        assert_eq!(keys.event(Pressed, b'*', 1100), []);
+        assert_eq!(
+            keys.event(Released, b'*', 1150),
+            [(Pressed, '*'), (Released, '*')]
+        );
+    }
+
+    #[test]
+    fn chord_without_suppress_passes_events() {
+        let mut keys = Keys::new();
+        assert_eq!(keys.event(Pressed, b'A', 0), [(Pressed, 'A')]);
+        assert_eq!(keys.event(Pressed, b'B', 10), [(Pressed, 'B')]);
+        assert_eq!(keys.poll(10), ["ab"]);
+        assert!(keys.poll(20).is_empty());
+        assert_eq!(keys.event(Released, b'A', 30), [(Released, 'A')]);
+        assert_eq!(keys.event(Pressed, b'A', 40), [(Pressed, 'A')]);
+        assert_eq!(keys.poll(40), ["ab"]);
+
+        assert!(keys.chords.remove("ab"));
+        assert!(!keys.chords.remove("ab"));
+    }
+}
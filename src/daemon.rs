@@ -0,0 +1,421 @@
+//! Sharing one keypad between processes over a Unix domain socket.
+//!
+//! The protocol is line based. The server sends
+//!
+//! ```text
+//! key <kind> <symbol> <timestamp_ms>    e.g. `key pressed A 1234`
+//! lock <mode>                           on connect and on every change
+//! error <message>                       in response to a bad command
+//! ```
+//!
+//! where kind is one of `pressed`, `released`, `long_press`, `repeat` and mode
+//! one of `locked`, `unlocked`, `power_only`, `custom`. Clients send
+//!
+//! ```text
+//! lock <mode>        set lock status (`custom` keeps the allowed keys)
+//! allow <symbols>    lock all keys except the given ones
+//! ```
+
+use anyhow::{Error, anyhow, bail};
+use i2cdev::core::{I2CDevice, I2CTransfer};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::Duration,
+};
+
+use super::{
+    Lock,
+    event::KeyEventKind,
+    keypad::Keypad,
+    layout::{KeyMask, Symbol},
+};
+
+/// Default socket path.
+pub const SOCKET: &str = "/run/keypad.sock";
+
+/// Clients not reading their events for this long are disconnected.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Message sent by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Key {
+        kind: KeyEventKind,
+        symbol: Symbol,
+        /// Milliseconds since the daemon initialized the keypad.
+        timestamp_ms: u64,
+    },
+    Lock(Lock),
+    Error(String),
+}
+
+impl Message {
+    /// Parse a message line.
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        let line = line.trim_end_matches('\n');
+        let (cmd, args) = line.split_once(' ').unwrap_or((line, ""));
+        match cmd {
+            "key" => {
+                let mut fields = args.split(' ');
+                let (Some(kind), Some(symbol), Some(timestamp), None) =
+                    (fields.next(), fields.next(), fields.next(), fields.next())
+                else {
+                    bail!("malformed key message `{line}`");
+                };
+                let kind = match kind {
+                    "pressed" => KeyEventKind::Pressed,
+                    "released" => KeyEventKind::Released,
+                    "long_press" => KeyEventKind::LongPress,
+                    "repeat" => KeyEventKind::Repeat,
+                    _ => bail!("unknown key event `{kind}`"),
+                };
+                let &[symbol] = symbol.as_bytes() else {
+                    bail!("malformed symbol `{symbol}`");
+                };
+                Ok(Self::Key {
+                    kind,
+                    symbol: Symbol::new(symbol),
+                    timestamp_ms: timestamp.parse()?,
+                })
+            }
+            "lock" => Ok(Self::Lock(parse_lock(args)?)),
+            "error" => Ok(Self::Error(args.to_owned())),
+            _ => bail!("unknown message `{line}`"),
+        }
+    }
+
+    /// Format the message as a line, including the newline.
+    pub fn to_line(&self) -> String {
+        match self {
+            Self::Key {
+                kind,
+                symbol,
+                timestamp_ms,
+            } => {
+                let kind = match kind {
+                    KeyEventKind::Pressed => "pressed",
+                    KeyEventKind::Released => "released",
+                    KeyEventKind::LongPress => "long_press",
+                    KeyEventKind::Repeat => "repeat",
+                };
+                format!("key {kind} {} {timestamp_ms}\n", symbol.chr() as char)
+            }
+            Self::Lock(lock) => format!("lock {}\n", lock_name(*lock)),
+            Self::Error(msg) => format!("error {msg}\n"),
+        }
+    }
+}
+
+fn lock_name(lock: Lock) -> &'static str {
+    match lock {
+        Lock::Locked => "locked",
+        Lock::Unlocked => "unlocked",
+        Lock::UnlockedPowerOnly => "power_only",
+        Lock::Custom => "custom",
+    }
+}
+
+fn parse_lock(name: &str) -> Result<Lock, Error> {
+    match name {
+        "locked" => Ok(Lock::Locked),
+        "unlocked" => Ok(Lock::Unlocked),
+        "power_only" => Ok(Lock::UnlockedPowerOnly),
+        "custom" => Ok(Lock::Custom),
+        _ => Err(anyhow!("unknown lock mode `{name}`")),
+    }
+}
+
+/// Connected clients.
+type Clients = Arc<Mutex<Vec<UnixStream>>>;
+
+/// Socket server broadcasting events of a keypad.
+pub struct Server<D> {
+    listener: UnixListener,
+    keypad: Arc<Keypad<D>>,
+    clients: Clients,
+}
+
+impl<D> Server<D>
+where
+    D: I2CDevice + for<'a> I2CTransfer<'a, Error = <D as I2CDevice>::Error> + Send + 'static,
+    <D as I2CDevice>::Error: Send + Sync + 'static,
+{
+    /// Listen on `path`, replacing a stale socket, and start forwarding events.
+    /// Fails if `path` is something else or another server listens on it.
+    ///
+    /// The server takes over the keypad event queue and the `OnLockChanged`
+    /// callback. Scanning must be started separately.
+    pub fn bind(path: impl AsRef<Path>, keypad: Arc<Keypad<D>>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        match fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => {
+                if UnixStream::connect(path).is_ok() {
+                    bail!("{} is in use by another server", path.display());
+                }
+                fs::remove_file(path)?;
+            }
+            Ok(_) => bail!("{} exists and is not a socket", path.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+        let listener = UnixListener::bind(path)?;
+        let clients = Clients::default();
+
+        // Lock changes may come from the scanning thread, which must not wait
+        // for slow clients, so all messages are written by a separate thread.
+        let (tx, rx) = mpsc::channel();
+        let c = clients.clone();
+        thread::Builder::new()
+            .name("keypad-broadcast".into())
+            .spawn(move || {
+                for msg in rx {
+                    broadcast(&c, &msg);
+                }
+            })?;
+
+        let t = tx.clone();
+        keypad.set_on_lock_changed(Box::new(move |lock| {
+            let _ = t.send(Message::Lock(lock));
+        }));
+
+        let kp = keypad.clone();
+        thread::Builder::new()
+            .name("keypad-events".into())
+            .spawn(move || {
+                loop {
+                    let event = kp.recv();
+                    let timestamp = event.timestamp.saturating_duration_since(kp.epoch());
+                    let msg = Message::Key {
+                        kind: event.kind,
+                        symbol: event.symbol,
+                        timestamp_ms: timestamp.as_millis() as u64,
+                    };
+                    if tx.send(msg).is_err() {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            listener,
+            keypad,
+            clients,
+        })
+    }
+
+    /// Accept clients. Returns only on error.
+    pub fn run(&self) -> Result<(), Error> {
+        for stream in self.listener.incoming() {
+            // Failure means the client went away while connecting.
+            let _ = self.accept(stream?);
+        }
+        Ok(())
+    }
+
+    fn accept(&self, mut stream: UnixStream) -> Result<(), Error> {
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let reader = stream.try_clone()?;
+        {
+            // Send the current status before any later change is broadcast.
+            let mut clients = self.clients.lock().unwrap();
+            stream.write_all(Message::Lock(self.keypad.get_lock()).to_line().as_bytes())?;
+            clients.push(stream.try_clone()?);
+        }
+
+        let keypad = self.keypad.clone();
+        thread::Builder::new()
+            .name("keypad-client".into())
+            .spawn(move || {
+                for line in BufReader::new(reader).lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    if let Err(e) = command(&keypad, &line) {
+                        let msg = Message::Error(format!("{e:#}"));
+                        if stream.write_all(msg.to_line().as_bytes()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            })?;
+        Ok(())
+    }
+}
+
+/// Execute a client command.
+fn command<D>(keypad: &Keypad<D>, line: &str) -> Result<(), Error>
+where
+    D: I2CDevice + for<'a> I2CTransfer<'a, Error = <D as I2CDevice>::Error>,
+    <D as I2CDevice>::Error: Send + Sync + 'static,
+{
+    let (cmd, args) = line.split_once(' ').unwrap_or((line, ""));
+    match cmd {
+        "lock" => keypad.set_lock(parse_lock(args)?),
+        "allow" => keypad.set_allowed(KeyMask::from_symbols(args.as_bytes())),
+        _ => bail!("unknown command `{cmd}`"),
+    }
+    Ok(())
+}
+
+/// Send message to all clients, dropping those that fail.
+fn broadcast(clients: &Clients, msg: &Message) {
+    let line = msg.to_line();
+    clients
+        .lock()
+        .unwrap()
+        .retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+}
+
+/// Connection to the keypad daemon.
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    /// Connect to the daemon listening on `path`, e.g. `SOCKET`.
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Self { reader, writer })
+    }
+
+    /// Wait for the next message. Returns `None` if the daemon closed the connection.
+    pub fn recv(&mut self) -> Result<Option<Message>, Error> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Message::parse(&line).map(Some)
+    }
+
+    /// Set lock status. The daemon confirms with a `Message::Lock` if it changed.
+    pub fn set_lock(&mut self, lock: Lock) -> Result<(), Error> {
+        writeln!(self.writer, "lock {}", lock_name(lock))?;
+        Ok(())
+    }
+
+    /// Lock all keys except `symbols`.
+    pub fn set_allowed(&mut self, symbols: &[Symbol]) -> Result<(), Error> {
+        let symbols: String = symbols.iter().map(|s| s.chr() as char).collect();
+        writeln!(self.writer, "allow {symbols}")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    #[test]
+    fn messages_round_trip() {
+        let kinds = [
+            KeyEventKind::Pressed,
+            KeyEventKind::Released,
+            KeyEventKind::LongPress,
+            KeyEventKind::Repeat,
+        ];
+        let locks = [
+            Lock::Locked,
+            Lock::Unlocked,
+            Lock::UnlockedPowerOnly,
+            Lock::Custom,
+        ];
+        let mut messages: Vec<Message> = kinds
+            .into_iter()
+            .map(|kind| Message::Key {
+                kind,
+                symbol: Symbol::new(b'#'),
+                timestamp_ms: 1234,
+            })
+            .collect();
+        messages.extend(locks.map(Message::Lock));
+        messages.push(Message::Error("unknown command `x`".into()));
+
+        for msg in messages {
+            assert_eq!(Message::parse(&msg.to_line()).unwrap(), msg);
+        }
+        assert_eq!(
+            Message::Key {
+                kind: KeyEventKind::LongPress,
+                symbol: Symbol::new(b'7'),
+                timestamp_ms: 17
+            }
+            .to_line(),
+            "key long_press 7 17\n"
+        );
+        assert_eq!(
+            Message::Lock(Lock::UnlockedPowerOnly).to_line(),
+            "lock power_only\n"
+        );
+    }
+
+    #[test]
+    fn malformed_messages() {
+        for line in [
+            "key pressed A",
+            "key pressed A 1 2",
+            "key held A 1",
+            "key pressed AB 1",
+            "key pressed A soon",
+            "lock open",
+            "hello",
+        ] {
+            assert!(Message::parse(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn commands() {
+        let keypad = Keypad::new(Emulator::new()).unwrap();
+
+        command(&keypad, "lock power_only").unwrap();
+        assert_eq!(keypad.get_lock(), Lock::UnlockedPowerOnly);
+        command(&keypad, "allow 12#").unwrap();
+        assert_eq!(keypad.get_lock(), Lock::Custom);
+        assert_eq!(keypad.get_allowed(), KeyMask::from_symbols(b"12#"));
+        command(&keypad, "lock locked").unwrap();
+        command(&keypad, "lock custom").unwrap();
+        assert_eq!(keypad.get_allowed(), KeyMask::from_symbols(b"12#"));
+
+        let error = |line| command(&keypad, line).unwrap_err().to_string();
+        assert_eq!(error("lock open"), "unknown lock mode `open`");
+        assert_eq!(error("unlock"), "unknown command `unlock`");
+        assert_eq!(keypad.get_lock(), Lock::Custom);
+    }
+
+    #[test]
+    fn bind_replaces_only_stale_sockets() {
+        let dir = std::env::temp_dir().join(format!("keypad-daemon-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let keypad = Arc::new(Keypad::new(Emulator::new()).unwrap());
+        let path = dir.join("keypad.sock");
+
+        // Left behind by a server that is gone.
+        drop(UnixListener::bind(&path).unwrap());
+        let server = Server::bind(&path, keypad.clone()).unwrap();
+        let error = |path: &Path| match Server::bind(path, keypad.clone()) {
+            Ok(_) => panic!("bound to {}", path.display()),
+            Err(e) => e.to_string(),
+        };
+        assert!(error(&path).ends_with("is in use by another server"));
+        assert!(UnixStream::connect(&path).is_ok());
+        drop(server);
+
+        let file = dir.join("keypad.conf");
+        fs::write(&file, "keep").unwrap();
+        assert!(error(&file).ends_with("exists and is not a socket"));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "keep");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
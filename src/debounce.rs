@@ -0,0 +1,58 @@
+/// Debounce setting.
+///
+/// A key changes its state only after the given number of consecutive scan
+/// samples disagree with the current state. One full scan of the matrix
+/// takes about 50 ms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Debounce {
+    /// Samples required to register a press.
+    pub press: u32,
+    /// Samples required to register a release.
+    pub release: u32,
+}
+
+impl Default for Debounce {
+    fn default() -> Self {
+        Self {
+            press: 1,
+            release: 1,
+        }
+    }
+}
+
+/// Debounced state of a single key.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Debouncer {
+    pressed: bool,
+    count: u32,
+}
+
+impl Debouncer {
+    /// Feed a raw sample. Returns `true` if the sample differs from the current
+    /// state and has been stable long enough to be committed with `set`.
+    pub fn sample(&mut self, pressed: bool, debounce: &Debounce) -> bool {
+        if pressed == self.pressed {
+            self.count = 0;
+            return false;
+        }
+        self.count = self.count.saturating_add(1);
+        let required = if pressed {
+            debounce.press
+        } else {
+            debounce.release
+        };
+        self.count >= required
+    }
+
+    /// Key is released and no press is pending.
+    #[inline]
+    pub fn is_idle(&self) -> bool {
+        !self.pressed && self.count == 0
+    }
+
+    /// Commit new state.
+    pub fn set(&mut self, pressed: bool) {
+        self.pressed = pressed;
+        self.count = 0;
+    }
+}
@@ -0,0 +1,456 @@
+//! Software emulation of the MCP23017 with the keypad matrix attached.
+//!
+//! The emulator implements `I2CDevice` and `I2CTransfer`, so it can be passed
+//! to `Keypad::new` instead of a real bus. Keys are "pressed" through a cloned
+//! handle while the driver owns the device.
+
+use anyhow::Error;
+use i2cdev::core::{I2CDevice, I2CMessage, I2CTransfer};
+use std::{
+    io,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use super::{interrupt::Interrupt, wiring::Wiring};
+
+/// Register kind, indexed as in BANK=1 mode (offset inside a port).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    IoDir = 0,
+    IPol = 1,
+    GpIntEn = 2,
+    DefVal = 3,
+    IntCon = 4,
+    IoCon = 5,
+    GpPu = 6,
+    IntF = 7,
+    IntCap = 8,
+    Gpio = 9,
+    OLat = 10,
+}
+
+const KINDS: [Kind; 11] = [
+    Kind::IoDir,
+    Kind::IPol,
+    Kind::GpIntEn,
+    Kind::DefVal,
+    Kind::IntCon,
+    Kind::IoCon,
+    Kind::GpPu,
+    Kind::IntF,
+    Kind::IntCap,
+    Kind::Gpio,
+    Kind::OLat,
+];
+
+const PORT_A: usize = 0;
+const PORT_B: usize = 1;
+
+/// IOCON bits.
+const IOCON_BANK: u8 = 0b1000_0000;
+const IOCON_SEQOP: u8 = 0b0010_0000;
+
+/// Internal chip state.
+#[derive(Debug)]
+struct State {
+    /// Register file, `regs[port][kind]`.
+    regs: [[u8; 11]; 2],
+    /// Address pointer.
+    pointer: u8,
+    /// Pressed keys, `matrix[pad][row][column]`.
+    matrix: [[[bool; 3]; 4]; 2],
+    /// Pin levels seen by the interrupt-on-change logic.
+    last: [u8; 2],
+    /// Number of upcoming bus transactions to fail.
+    glitches: u32,
+    /// Connection of the matrix to the pins.
+    wiring: Wiring,
+    /// Port B lines connected to each other by a wiring fault.
+    shorts: Vec<(usize, usize)>,
+    /// Port B lines connected to ground by a wiring fault.
+    grounded: u8,
+}
+
+impl State {
+    fn new(wiring: Wiring) -> Self {
+        let mut regs = [[0; 11]; 2];
+        // All pins are inputs after power-on reset.
+        regs[PORT_A][Kind::IoDir as usize] = 0xFF;
+        regs[PORT_B][Kind::IoDir as usize] = 0xFF;
+        Self {
+            regs,
+            pointer: 0,
+            matrix: Default::default(),
+            last: [0xFF; 2],
+            glitches: 0,
+            wiring,
+            shorts: Vec::new(),
+            grounded: 0,
+        }
+    }
+
+    fn bank(&self) -> bool {
+        self.regs[PORT_A][Kind::IoCon as usize] & IOCON_BANK != 0
+    }
+
+    /// Decode register address according to the current IOCON.BANK.
+    fn decode(&self, addr: u8) -> Option<(usize, Kind)> {
+        let (port, idx) = if self.bank() {
+            ((addr >> 4) as usize, (addr & 0x0F) as usize)
+        } else {
+            ((addr & 1) as usize, (addr >> 1) as usize)
+        };
+        if port > PORT_B {
+            return None;
+        }
+        KINDS.get(idx).map(|&kind| (port, kind))
+    }
+
+    /// Compute the register address that follows `addr` in sequential mode.
+    fn next(&self, addr: u8) -> u8 {
+        if self.regs[PORT_A][Kind::IoCon as usize] & IOCON_SEQOP != 0 {
+            addr
+        } else if self.bank() {
+            (addr & 0x10) | (((addr & 0x0F) + 1) % KINDS.len() as u8)
+        } else {
+            (addr + 1) % 0x16
+        }
+    }
+
+    fn write_reg(&mut self, addr: u8, val: u8) {
+        let Some((port, kind)) = self.decode(addr) else {
+            return;
+        };
+        match kind {
+            // IOCON is shared by both ports.
+            Kind::IoCon => {
+                self.regs[PORT_A][kind as usize] = val;
+                self.regs[PORT_B][kind as usize] = val;
+            }
+            // Writing GPIO modifies the output latch.
+            Kind::Gpio | Kind::OLat => self.regs[port][Kind::OLat as usize] = val,
+            // Read-only registers.
+            Kind::IntF | Kind::IntCap => {}
+            _ => self.regs[port][kind as usize] = val,
+        }
+    }
+
+    fn read_reg(&mut self, addr: u8) -> u8 {
+        let Some((port, kind)) = self.decode(addr) else {
+            return 0;
+        };
+        match kind {
+            Kind::Gpio | Kind::IntCap => {
+                // Reading GPIO or INTCAP clears the interrupt condition.
+                let val = if kind == Kind::Gpio {
+                    self.pins(port) ^ self.regs[port][Kind::IPol as usize]
+                } else {
+                    self.regs[port][kind as usize]
+                };
+                self.regs[port][Kind::IntF as usize] = 0;
+                val
+            }
+            _ => self.regs[port][kind as usize],
+        }
+    }
+
+    /// Evaluate interrupt-on-change logic after any change of the chip state.
+    fn update_interrupts(&mut self) {
+        let levels = self.levels();
+        for (port, &pins) in levels.iter().enumerate() {
+            let regs = &mut self.regs[port];
+            let intcon = regs[Kind::IntCon as usize];
+            // Compare either against DEFVAL or against the previous value.
+            let reference = (regs[Kind::DefVal as usize] & intcon) | (self.last[port] & !intcon);
+            let fired = (pins ^ reference) & regs[Kind::GpIntEn as usize];
+            if fired != 0 && regs[Kind::IntF as usize] == 0 {
+                regs[Kind::IntCap as usize] = pins;
+                regs[Kind::IntF as usize] = fired;
+            }
+            self.last[port] = pins;
+        }
+    }
+
+    /// State of the INTA output.
+    fn int_a(&self) -> bool {
+        self.regs[PORT_A][Kind::IntF as usize] & self.regs[PORT_A][Kind::GpIntEn as usize] != 0
+    }
+
+    /// Logical levels of the pins of a port.
+    fn pins(&self, port: usize) -> u8 {
+        let [a, b] = self.levels();
+        if port == PORT_A { a } else { b }
+    }
+
+    /// Compute pin levels of both ports taking pressed keys into account.
+    ///
+    /// A pressed key connects a port B (row) pin with a port A (column) pin.
+    /// A pin that is an input follows a connected pin driven low; otherwise a
+    /// port A pin is held high by the pull-up or the residual charge of the
+    /// column, a port B pin only by the pull-up.
+    fn levels(&self) -> [u8; 2] {
+        let dir_a = self.regs[PORT_A][Kind::IoDir as usize];
+        let dir_b = self.regs[PORT_B][Kind::IoDir as usize];
+        let out_a = self.regs[PORT_A][Kind::OLat as usize];
+        let out_b = self.regs[PORT_B][Kind::OLat as usize];
+        let pup_b = self.regs[PORT_B][Kind::GpPu as usize];
+        let low_a = !dir_a & !out_a;
+        let mut low_b = (!dir_b & !out_b) | self.grounded;
+        // A low line pulls down the lines shorted to it, possibly in a chain.
+        for _ in 0..self.shorts.len() {
+            for &(line, other) in &self.shorts {
+                if low_b & (1 << line | 1 << other) != 0 {
+                    low_b |= 1 << line | 1 << other;
+                }
+            }
+        }
+
+        let mut a = dir_a | out_a;
+        let mut b = ((dir_b & pup_b) | (!dir_b & out_b)) & !low_b;
+        for (scanrow, &(pad, row)) in self.wiring.rows().iter().enumerate() {
+            for &(bit, column) in self.wiring.columns() {
+                if !self.matrix[pad][row][column] {
+                    continue;
+                }
+                let pin_a = 1u8 << bit;
+                let pin_b = 1u8 << scanrow;
+                if low_b & pin_b != 0 && dir_a & pin_a != 0 {
+                    a &= !pin_a;
+                }
+                if low_a & pin_a != 0 && dir_b & pin_b != 0 {
+                    b &= !pin_b;
+                }
+            }
+        }
+        [a, b]
+    }
+
+    /// Fail the current bus transaction if a glitch is pending.
+    fn nack(&mut self) -> Result<(), io::Error> {
+        if self.glitches == 0 {
+            return Ok(());
+        }
+        self.glitches -= 1;
+        Err(io::Error::from_raw_os_error(libc::EREMOTEIO))
+    }
+}
+
+/// Emulated MCP23017 with a virtual 2×4×3 key matrix.
+///
+/// Clones share the same chip, so a test may keep one handle to press keys
+/// while the driver scans through another.
+#[derive(Debug, Clone)]
+pub struct Emulator {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled on every change of the chip state.
+    changed: Condvar,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Emulator {
+    /// Create a chip in its power-on state with no keys pressed.
+    pub fn new() -> Self {
+        Self::with_wiring(Wiring::default())
+    }
+
+    /// Same as `new` with the matrix connected according to `wiring`.
+    pub fn with_wiring(wiring: Wiring) -> Self {
+        let shared = Shared {
+            state: Mutex::new(State::new(wiring)),
+            changed: Condvar::new(),
+        };
+        Self {
+            shared: Arc::new(shared),
+        }
+    }
+
+    /// Press the key at the given matrix position.
+    pub fn press(&self, pad: usize, row: usize, column: usize) {
+        self.modify(|state| state.matrix[pad][row][column] = true)
+    }
+
+    /// Release the key at the given matrix position.
+    pub fn release(&self, pad: usize, row: usize, column: usize) {
+        self.modify(|state| state.matrix[pad][row][column] = false)
+    }
+
+    /// Release all keys.
+    pub fn release_all(&self) {
+        self.modify(|state| state.matrix = Default::default())
+    }
+
+    /// Connect two port B lines, as a solder bridge would.
+    pub fn short_rows(&self, line: usize, other: usize) {
+        self.modify(|state| state.shorts.push((line, other)))
+    }
+
+    /// Connect a port B line to ground.
+    pub fn ground_row(&self, line: usize) {
+        self.modify(|state| state.grounded |= 1 << line)
+    }
+
+    /// Simulate a power cycle of the chip. Pressed keys and wiring faults are kept.
+    pub fn power_cycle(&self) {
+        self.modify(|state| {
+            let mut new = State::new(state.wiring.clone());
+            new.matrix = state.matrix;
+            new.shorts = std::mem::take(&mut state.shorts);
+            new.grounded = state.grounded;
+            *state = new;
+        })
+    }
+
+    /// Make the next `count` bus transactions fail as if the chip did not acknowledge.
+    pub fn glitch(&self, count: u32) {
+        self.modify(|state| state.glitches = count)
+    }
+
+    /// Get the INTA line of the chip.
+    pub fn interrupt(&self) -> EmulatorInterrupt {
+        EmulatorInterrupt {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Peek at a register without side effects, addressed as in BANK=1 mode.
+    pub fn register(&self, addr: u8) -> u8 {
+        let state = self.shared.state.lock().unwrap();
+        let port = (addr >> 4) as usize & 1;
+        let idx = (addr & 0x0F) as usize;
+        match KINDS.get(idx) {
+            Some(Kind::Gpio) => state.pins(port),
+            Some(&kind) => state.regs[port][kind as usize],
+            None => 0,
+        }
+    }
+
+    /// Apply a change to the chip state and re-evaluate interrupts.
+    fn modify<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        let mut state = self.shared.state.lock().unwrap();
+        let ret = f(&mut state);
+        state.update_interrupts();
+        self.shared.changed.notify_all();
+        ret
+    }
+}
+
+/// INTA line of the emulated chip.
+#[derive(Debug, Clone)]
+pub struct EmulatorInterrupt {
+    shared: Arc<Shared>,
+}
+
+impl Interrupt for EmulatorInterrupt {
+    fn wait(&mut self, timeout: Duration) -> Result<bool, Error> {
+        let state = self.shared.state.lock().unwrap();
+        let (state, _) = self
+            .shared
+            .changed
+            .wait_timeout_while(state, timeout, |state| !state.int_a())
+            .unwrap();
+        Ok(state.int_a())
+    }
+}
+
+impl I2CDevice for Emulator {
+    type Error = io::Error;
+
+    fn read(&mut self, data: &mut [u8]) -> Result<(), io::Error> {
+        self.modify(|state| {
+            state.nack()?;
+            for byte in data.iter_mut() {
+                let addr = state.pointer;
+                *byte = state.read_reg(addr);
+                state.pointer = state.next(addr);
+            }
+            Ok(())
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        let Some((&addr, values)) = data.split_first() else {
+            return Ok(());
+        };
+        self.modify(|state| {
+            state.nack()?;
+            state.pointer = addr;
+            for &val in values {
+                let addr = state.pointer;
+                state.write_reg(addr, val);
+                state.pointer = state.next(addr);
+            }
+            Ok(())
+        })
+    }
+
+    fn smbus_write_quick(&mut self, _bit: bool) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn smbus_read_block_data(&mut self, _register: u8) -> Result<Vec<u8>, io::Error> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn smbus_read_i2c_block_data(&mut self, register: u8, len: u8) -> Result<Vec<u8>, io::Error> {
+        let mut buf = vec![0; len as usize];
+        self.write(&[register])?;
+        self.read(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn smbus_write_block_data(&mut self, _register: u8, _values: &[u8]) -> Result<(), io::Error> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn smbus_write_i2c_block_data(&mut self, register: u8, values: &[u8]) -> Result<(), io::Error> {
+        let mut buf = vec![register];
+        buf.extend_from_slice(values);
+        self.write(&buf)
+    }
+
+    fn smbus_process_block(&mut self, _register: u8, _values: &[u8]) -> Result<Vec<u8>, io::Error> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Message of an emulated I2C transfer.
+pub enum Message<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+impl<'a> I2CMessage<'a> for Message<'a> {
+    fn read(data: &'a mut [u8]) -> Self {
+        Self::Read(data)
+    }
+
+    fn write(data: &'a [u8]) -> Self {
+        Self::Write(data)
+    }
+}
+
+impl<'a> I2CTransfer<'a> for Emulator {
+    type Error = io::Error;
+    type Message = Message<'a>;
+
+    fn transfer(&mut self, msgs: &'a mut [Self::Message]) -> Result<u32, io::Error> {
+        for msg in msgs.iter_mut() {
+            match msg {
+                Message::Read(data) => I2CDevice::read(self, data)?,
+                Message::Write(data) => I2CDevice::write(self, data)?,
+            }
+        }
+        Ok(msgs.len() as u32)
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::Error;
+use i2cdev::linux::LinuxI2CError;
+use std::{fmt, io, str::Utf8Error};
+
+/// Error code returned by the C API.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KpError {
+    Ok = 0,
+    /// `keypad_init` has not been called or failed.
+    NotInitialized = 1,
+    InvalidArgument = 2,
+    /// Device or file does not exist.
+    NotFound = 3,
+    PermissionDenied = 4,
+    /// Device is in use.
+    Busy = 5,
+    /// Chip did not acknowledge its address.
+    Nack = 6,
+    Timeout = 7,
+    /// Other I/O error.
+    Io = 8,
+    AlreadyRunning = 9,
+    NotRunning = 10,
+    /// Event queue is empty.
+    NoEvent = 11,
+    Unknown = 255,
+}
+
+impl KpError {
+    /// Find the error code for the first recognized cause of the error.
+    pub fn of(e: &Error) -> Self {
+        for cause in e.chain() {
+            if let Some(&code) = cause.downcast_ref::<KpError>() {
+                return code;
+            }
+            if let Some(e) = cause.downcast_ref::<LinuxI2CError>() {
+                return match e {
+                    LinuxI2CError::Errno(errno) => Self::from_errno(*errno),
+                    LinuxI2CError::Io(e) => Self::from_io(e),
+                };
+            }
+            if let Some(e) = cause.downcast_ref::<io::Error>() {
+                return Self::from_io(e);
+            }
+            if cause.is::<Utf8Error>() {
+                return Self::InvalidArgument;
+            }
+        }
+        Self::Unknown
+    }
+
+    fn from_io(e: &io::Error) -> Self {
+        if let Some(errno) = e.raw_os_error() {
+            return Self::from_errno(errno);
+        }
+        match e.kind() {
+            io::ErrorKind::NotFound => Self::NotFound,
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied,
+            io::ErrorKind::TimedOut => Self::Timeout,
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => Self::InvalidArgument,
+            _ => Self::Io,
+        }
+    }
+
+    fn from_errno(errno: i32) -> Self {
+        match errno {
+            libc::ENOENT | libc::ENODEV => Self::NotFound,
+            libc::EACCES | libc::EPERM => Self::PermissionDenied,
+            libc::EBUSY => Self::Busy,
+            // I2C bus drivers report a missing acknowledge with one of these.
+            libc::ENXIO | libc::EREMOTEIO => Self::Nack,
+            libc::ETIMEDOUT => Self::Timeout,
+            libc::EINVAL => Self::InvalidArgument,
+            _ => Self::Io,
+        }
+    }
+}
+
+impl fmt::Display for KpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Self::Ok => "success",
+            Self::NotInitialized => "keypad not initialized",
+            Self::InvalidArgument => "invalid argument",
+            Self::NotFound => "not found",
+            Self::PermissionDenied => "permission denied",
+            Self::Busy => "device busy",
+            Self::Nack => "chip not responding",
+            Self::Timeout => "timeout",
+            Self::Io => "I/O error",
+            Self::AlreadyRunning => "keypad already running",
+            Self::NotRunning => "keypad not running",
+            Self::NoEvent => "no event",
+            Self::Unknown => "unknown error",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for KpError {}
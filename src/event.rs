@@ -0,0 +1,44 @@
+use anyhow::Error;
+use std::time::Instant;
+
+use super::layout::Symbol;
+
+/// Kind of key event.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEventKind {
+    Pressed = 0,
+    Released = 1,
+    LongPress = 2,
+    Repeat = 3,
+}
+
+/// Key event delivered through the event queue and the `OnEvent` callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub symbol: Symbol,
+    pub kind: KeyEventKind,
+    /// Time the key was sampled.
+    pub timestamp: Instant,
+    /// Matrix position of the key.
+    pub pad: usize,
+    pub row: usize,
+    pub column: usize,
+}
+
+/// Progress of the recovery from an I2C bus error.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryEvent {
+    /// Bus transfer failed, scanning is suspended.
+    BusError = 0,
+    /// Chip lost its configuration (e.g. power cycle) and was re-initialized.
+    ChipReset = 1,
+    /// Scanning resumed after a bus error.
+    Recovered = 2,
+}
+
+/// Consumer of key events other than callbacks and the event queue, e.g. `uinput::Uinput`.
+pub trait Output: Send {
+    fn event(&mut self, event: &KeyEvent) -> Result<(), Error>;
+}
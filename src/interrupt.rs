@@ -0,0 +1,54 @@
+use anyhow::Error;
+use gpio_cdev::{Chip, EventRequestFlags, LineEventHandle, LineRequestFlags};
+use std::{os::fd::AsRawFd, path::Path, time::Duration};
+
+/// Source of the chip interrupt signal (INTA).
+pub trait Interrupt: Send {
+    /// Wait until the interrupt line becomes active. Returns `false` on timeout.
+    fn wait(&mut self, timeout: Duration) -> Result<bool, Error>;
+}
+
+/// INTA connected to a GPIO line, accessed through the Linux GPIO character device.
+pub struct GpioInterrupt {
+    events: LineEventHandle,
+}
+
+impl GpioInterrupt {
+    /// Request falling edge events of `line` on the given GPIO chip (e.g. `/dev/gpiochip0`).
+    pub fn new(chip: impl AsRef<Path>, line: u32) -> Result<Self, Error> {
+        let mut chip = Chip::new(chip)?;
+        let events = chip.get_line(line)?.events(
+            LineRequestFlags::INPUT,
+            EventRequestFlags::FALLING_EDGE,
+            "keypad",
+        )?;
+        Ok(Self { events })
+    }
+}
+
+impl Interrupt for GpioInterrupt {
+    fn wait(&mut self, timeout: Duration) -> Result<bool, Error> {
+        let mut fds = [libc::pollfd {
+            fd: self.events.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout) };
+        match ret {
+            0 => Ok(false),
+            n if n > 0 => {
+                self.events.get_event()?;
+                Ok(true)
+            }
+            _ => {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    Ok(false)
+                } else {
+                    Err(err.into())
+                }
+            }
+        }
+    }
+}
@@ -1,36 +1,43 @@
-use anyhow::Error;
+use anyhow::{Context, Error, anyhow, bail};
 use i2cdev::{
     core::{I2CDevice, I2CMessage, I2CTransfer},
     linux::LinuxI2CDevice,
 };
 use std::{
+    collections::{HashMap, VecDeque},
+    mem::ManuallyDrop,
+    path::PathBuf,
     sync::{
-        Mutex,
+        Arc, Condvar, Mutex,
         atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, SyncSender, TrySendError, sync_channel},
     },
-    thread::sleep,
-    time::Duration,
+    thread::{self, JoinHandle, ThreadId, sleep},
+    time::{Duration, Instant},
 };
 
 use super::{
     AtomicLock, Lock,
-    layout::{Symbol, translate},
+    chord::{Chord, Chords},
+    debounce::{Debounce, Debouncer},
+    error::KpError,
+    event::{KeyEvent, KeyEventKind, Output, RecoveryEvent},
+    interrupt::Interrupt,
+    layout::{Key, KeyMask, Keymap, Symbol},
+    text::{MultiTap, TextEntry, TextEvent},
+    wiring::Wiring,
 };
 
 const DEVICE: &str = "/dev/i2c-1";
 const ADDRESS: u16 = 0b_010_0_000;
-
-const ROWS: [(usize, usize); 8] = [
-    (1, 1),
-    (1, 4),
-    (1, 3),
-    (2, 2),
-    (2, 1),
-    (2, 4),
-    (2, 3),
-    (1, 2),
-];
-const COLS: [usize; 3] = [2, 1, 3];
+const EVENT_QUEUE: usize = 64;
+/// How often to check for stop request while waiting for an interrupt.
+const IDLE_POLL: Duration = Duration::from_millis(100);
+/// IOCON value while the chip keeps our configuration (BANK=1).
+///
+/// The whole value is compared: after a reset the chip is in BANK=0 mode,
+/// where writing OLATA actually writes IOCON and may set BANK again.
+const IOCON: u8 = 0b1000_0000;
 
 /// Register of the chip (selection).
 #[allow(dead_code)]
@@ -43,43 +50,427 @@ enum Reg {
     PupB = 0x16,
     InpA = 0x09,
     InpB = 0x19,
+    GpIntEnA = 0x02,
+    DefValA = 0x03,
+    IntConA = 0x04,
+    IntCapA = 0x08,
+    IoCon = 0x05,
 }
 
-pub struct Keypad {
-    dev: Mutex<LinuxI2CDevice>,
+pub struct Keypad<D = LinuxI2CDevice> {
+    dev: Mutex<D>,
     lock_state: AtomicLock,
-    on_pressed: Mutex<Option<Box<dyn FnMut(Symbol) + Send>>>,
-    on_released: Mutex<Option<Box<dyn FnMut(Symbol) + Send>>>,
+    /// Keys active in `Lock::Custom` mode.
+    allowed: Mutex<KeyMask>,
+    auto_lock: Mutex<Option<AutoLock>>,
+    /// Time of the last key press, including rejected ones.
+    last_input: Mutex<Instant>,
+    /// Keys held down, in the order their `Pressed` events were delivered.
+    pressed: Mutex<Vec<Symbol>>,
+    /// Keys held down, including the ones whose events are not delivered.
+    held: Mutex<KeyMask>,
+    chords: Mutex<Chords>,
+    text: Mutex<Option<TextEntry>>,
+    unlock: Mutex<Unlock>,
+    debounce: Mutex<Debounce>,
+    keymap: Mutex<Keymap>,
+    wiring: Mutex<Wiring>,
+    long_press: Mutex<HashMap<Symbol, Duration>>,
+    repeat: Mutex<Option<Repeat>>,
+    recovery: Mutex<Option<Recovery>>,
+    on_pressed: Slot<Callback>,
+    on_released: Slot<Callback>,
+    on_long_press: Slot<Callback>,
+    on_repeat: Slot<Callback>,
+    on_event: Slot<EventCallback>,
+    on_recovery: Slot<RecoveryCallback>,
+    on_lock_changed: Slot<LockCallback>,
+    lock_notify: Mutex<LockNotify>,
+    on_rejected: Slot<RejectedCallback>,
+    on_chord: Slot<ChordCallback>,
+    on_text: Slot<TextCallback>,
+    output: Slot<Box<dyn Output>>,
+    irq: Mutex<Option<Box<dyn Interrupt>>>,
+    epoch: Instant,
+    event_tx: SyncSender<KeyEvent>,
+    event_rx: Mutex<Receiver<KeyEvent>>,
     stop: AtomicBool,
+    /// Thread running `scan`, if any.
+    scanner: Mutex<Option<ThreadId>>,
+    scanner_done: Condvar,
 }
 
-impl Keypad {
-    /// Open and initialize keypad.
-    pub fn open() -> Result<Self, Error> {
-        let mut dev = LinuxI2CDevice::new(DEVICE, ADDRESS)?;
+/// Key event callback.
+pub type Callback = Box<dyn FnMut(Symbol) + Send>;
+
+/// Callback receiving every key event with its details.
+pub type EventCallback = Box<dyn FnMut(&KeyEvent) + Send>;
+
+/// Chord callback, with the chord name.
+pub type ChordCallback = Box<dyn FnMut(&str) + Send>;
+
+/// Text entry callback.
+pub type TextCallback = Box<dyn FnMut(TextEvent) + Send>;
+
+/// Bus error recovery callback.
+pub type RecoveryCallback = Box<dyn FnMut(RecoveryEvent) + Send>;
+
+/// Lock status change callback.
+pub type LockCallback = Box<dyn FnMut(Lock) + Send>;
+
+/// Callback for a key pressed while locked, with the lock status at that time.
+pub type RejectedCallback = Box<dyn FnMut(Symbol, Lock) + Send>;
+
+/// Replaceable callback.
+///
+/// The callback is invoked without holding the slot lock, so it may replace
+/// itself or any other callback.
+pub(crate) struct Slot<F>(Mutex<Option<Arc<Mutex<F>>>>);
+
+impl<F> Default for Slot<F> {
+    fn default() -> Self {
+        Self(Mutex::new(None))
+    }
+}
+
+impl<F> Slot<F> {
+    /// Replace the callback.
+    pub fn set(&self, cb: F) {
+        *self.0.lock().unwrap() = Some(Arc::new(Mutex::new(cb)))
+    }
+
+    /// Get the current callback.
+    pub fn get(&self) -> Option<Arc<Mutex<F>>> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Scanner state of a single key.
+#[derive(Debug, Default)]
+struct KeyState {
+    debouncer: Debouncer,
+    /// Time of the (debounced) press, `None` if the key is released.
+    pressed_at: Option<Instant>,
+    /// Long press has been reported for the current press.
+    long_pressed: bool,
+    /// Time of the next auto-repeat event.
+    next_repeat: Option<Instant>,
+    /// Current press was rejected because the keypad is locked.
+    rejected: bool,
+}
+
+impl KeyState {
+    /// Key is released and no press is pending.
+    fn is_idle(&self) -> bool {
+        self.pressed_at.is_none() && self.debouncer.is_idle()
+    }
+}
+
+/// Auto-repeat setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repeat {
+    /// Delay between the press and the first repeat.
+    pub delay: Duration,
+    /// Interval between subsequent repeats.
+    pub interval: Duration,
+}
+
+/// Automatic lock setting.
+#[derive(Debug, Clone, Copy)]
+pub struct AutoLock {
+    /// Inactivity time after which the unlocked keypad locks itself.
+    pub timeout: Duration,
+    /// Lock status to switch to.
+    pub lock: Lock,
+}
+
+/// Lock status changes waiting for the `OnLockChanged` callback.
+#[derive(Debug, Default)]
+struct LockNotify {
+    /// Some thread is calling the callback.
+    busy: bool,
+    queue: VecDeque<Lock>,
+}
+
+/// Unlock sequence and the keys recently pressed while not unlocked.
+#[derive(Debug, Default)]
+struct Unlock {
+    sequence: Vec<Symbol>,
+    entered: VecDeque<Symbol>,
+}
+
+impl Unlock {
+    /// Add a pressed key. Returns `true` if the sequence is complete.
+    fn enter(&mut self, chr: Symbol) -> bool {
+        if self.sequence.is_empty() {
+            return false;
+        }
+        if self.entered.len() == self.sequence.len() {
+            self.entered.pop_front();
+        }
+        self.entered.push_back(chr);
+        if self.entered.iter().eq(self.sequence.iter()) {
+            self.entered.clear();
+            return true;
+        }
+        false
+    }
+}
+
+/// Result of `Keypad::check_rows`, rows given as `(pad, row)`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RowCheck {
+    /// Rows connected to each other.
+    pub shorts: Vec<((usize, usize), (usize, usize))>,
+    /// Rows reading low with nothing driving them.
+    pub grounded: Vec<(usize, usize)>,
+}
+
+/// Bus error recovery policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recovery {
+    /// Number of consecutive recovery attempts before scanning fails.
+    pub retries: u32,
+    /// Delay before each attempt.
+    pub delay: Duration,
+}
+
+impl Default for Recovery {
+    fn default() -> Self {
+        Self {
+            retries: 10,
+            delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Keypad configuration.
+pub struct Builder {
+    device: PathBuf,
+    address: u16,
+    event_queue: usize,
+    irq: Option<Box<dyn Interrupt>>,
+    recovery: Option<Recovery>,
+    wiring: Wiring,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            device: PathBuf::from(DEVICE),
+            address: ADDRESS,
+            event_queue: EVENT_QUEUE,
+            irq: None,
+            recovery: Some(Recovery::default()),
+            wiring: Wiring::default(),
+        }
+    }
+}
+
+impl Builder {
+    /// Create configuration with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply a command line option shared by the tools: `--device PATH`,
+    /// `--address ADDR` (decimal or `0x` hexadecimal) or `--wiring PATH`.
+    pub fn option(self, name: &str, value: &str) -> Result<Self, Error> {
+        Ok(match name {
+            "--device" => self.device(value),
+            "--address" => self.address(parse_address(value)?),
+            "--wiring" => self.wiring(Wiring::load(value).context("cannot load wiring")?),
+            _ => bail!("unknown option `{name}`"),
+        })
+    }
+
+    /// Set I2C bus device path.
+    pub fn device(mut self, path: impl Into<PathBuf>) -> Self {
+        self.device = path.into();
+        self
+    }
+
+    /// Set I2C address of the chip.
+    pub fn address(mut self, address: u16) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Set capacity of the event queue. The oldest event is dropped when the queue is full.
+    pub fn event_queue(mut self, capacity: usize) -> Self {
+        self.event_queue = capacity;
+        self
+    }
 
-        // Set MCP23017 to predictable state.
-        dev.write(&[0x05, 0b1000_0000])?; // IOCON BANK=1
-        dev.write(&[0x0A, 0b1000_0000])?; // IOCON BANK=1
-        dev.write(&[0x0A, 0b0000_0000])?; // OLATA
-        dev.write(&[0x12, 0b0000_0000])?; // INTCONB (aka 0x05)
+    /// Use the chip interrupt to sleep while no key is pressed.
+    pub fn interrupt(mut self, irq: impl Interrupt + 'static) -> Self {
+        self.irq = Some(Box::new(irq));
+        self
+    }
+
+    /// Set connection of the key matrix to the chip.
+    pub fn wiring(mut self, wiring: Wiring) -> Self {
+        self.wiring = wiring;
+        self
+    }
+
+    /// Set bus error recovery policy. `None` makes scanning fail on the first error.
+    pub fn recovery(mut self, recovery: Option<Recovery>) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Open and initialize keypad on the configured bus.
+    pub fn open(self) -> Result<Keypad, Error> {
+        let dev = LinuxI2CDevice::new(&self.device, self.address)?;
+        self.build(dev)
+    }
+
+    /// Initialize keypad on an already opened device.
+    pub fn build<D>(self, mut dev: D) -> Result<Keypad<D>, Error>
+    where
+        D: I2CDevice + for<'a> I2CTransfer<'a, Error = <D as I2CDevice>::Error>,
+        <D as I2CDevice>::Error: Send + Sync + 'static,
+    {
+        configure(&mut dev)?;
 
         let dev = Mutex::new(dev);
-        Ok(Self {
+        let (event_tx, event_rx) = sync_channel(self.event_queue);
+        Ok(Keypad {
             dev,
+            irq: Mutex::new(self.irq),
+            epoch: Instant::now(),
+            event_tx,
+            event_rx: Mutex::new(event_rx),
             stop: AtomicBool::new(false),
+            scanner: Mutex::new(None),
+            scanner_done: Condvar::new(),
             lock_state: AtomicLock::new(Lock::Unlocked),
-            on_pressed: Mutex::new(None),
-            on_released: Mutex::new(None),
+            allowed: Mutex::new(KeyMask::empty()),
+            auto_lock: Mutex::new(None),
+            last_input: Mutex::new(Instant::now()),
+            pressed: Mutex::new(Vec::new()),
+            held: Mutex::new(KeyMask::empty()),
+            chords: Mutex::new(Chords::default()),
+            text: Mutex::new(None),
+            unlock: Mutex::new(Unlock::default()),
+            debounce: Mutex::new(Debounce::default()),
+            keymap: Mutex::new(Keymap::default()),
+            wiring: Mutex::new(self.wiring),
+            long_press: Mutex::new(HashMap::new()),
+            repeat: Mutex::new(None),
+            recovery: Mutex::new(self.recovery),
+            on_pressed: Slot::default(),
+            on_released: Slot::default(),
+            on_long_press: Slot::default(),
+            on_repeat: Slot::default(),
+            on_event: Slot::default(),
+            on_recovery: Slot::default(),
+            on_lock_changed: Slot::default(),
+            lock_notify: Mutex::new(LockNotify::default()),
+            on_rejected: Slot::default(),
+            on_chord: Slot::default(),
+            on_text: Slot::default(),
+            output: Slot::default(),
         })
     }
+}
+
+/// Parse chip address, decimal or `0x` hexadecimal.
+fn parse_address(value: &str) -> Result<u16, Error> {
+    let address = match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    address.with_context(|| format!("invalid address `{value}`"))
+}
+
+impl Keypad {
+    /// Open and initialize keypad with default settings.
+    pub fn open() -> Result<Self, Error> {
+        Builder::new().open()
+    }
+
+    /// Create keypad configuration.
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+}
+
+impl<D> Keypad<D>
+where
+    D: I2CDevice + for<'a> I2CTransfer<'a, Error = <D as I2CDevice>::Error>,
+    <D as I2CDevice>::Error: Send + Sync + 'static,
+{
+    /// Initialize keypad on an already opened device with default settings.
+    pub fn new(dev: D) -> Result<Self, Error> {
+        Builder::new().build(dev)
+    }
 
     /// Run scanning thread.
+    ///
+    /// Callbacks are invoked from this thread without any internal locks
+    /// held, so they may call other methods including `stop`.
     pub fn scan(&self) -> Result<(), Error> {
-        let mut matrix: [[[bool; 3]; 4]; 2] = Default::default();
-        let mut dev = self.dev.lock().unwrap();
         self.stop.store(false, Ordering::SeqCst);
+        self.run()
+    }
+
+    /// Run scanning in a background thread.
+    pub fn spawn(self: &Arc<Self>) -> Result<ScanHandle<D>, Error>
+    where
+        D: Send + 'static,
+    {
+        // Reset here rather than in the thread, so an early `stop` is not lost.
+        self.stop.store(false, Ordering::SeqCst);
+        let keypad = self.clone();
+        let thread = thread::Builder::new()
+            .name("keypad".into())
+            .spawn(move || keypad.run())?;
+        Ok(ScanHandle {
+            keypad: self.clone(),
+            thread: Some(thread),
+        })
+    }
+
+    /// Scanning loop.
+    fn run(&self) -> Result<(), Error> {
+        let mut matrix: [[[KeyState; 3]; 4]; 2] = Default::default();
+        // Registered first so `stop` waits for the device to be released too.
+        let _scanner = ScannerGuard::new(self);
+        let mut dev = self.dev.lock().unwrap();
+
+        let mut result = self.prepare(&mut dev);
+        let mut failures = 0;
+        while !self.stop.load(Ordering::SeqCst) {
+            result = match result {
+                Ok(()) => {
+                    let result = self.sweep(&mut dev, &mut matrix);
+                    if result.is_ok() && failures > 0 {
+                        failures = 0;
+                        self.report(RecoveryEvent::Recovered);
+                    }
+                    result
+                }
+                // Only bus errors are worth retrying.
+                Err(e) if e.is::<<D as I2CDevice>::Error>() => {
+                    let recovery = *self.recovery.lock().unwrap();
+                    let Some(recovery) = recovery.filter(|r| failures < r.retries) else {
+                        return Err(e);
+                    };
+                    failures += 1;
+                    self.report(RecoveryEvent::BusError);
+                    sleep(recovery.delay);
+                    self.recover(&mut dev)
+                }
+                Err(e) => return Err(e),
+            };
+        }
+        Ok(())
+    }
 
+    /// Pre-charge lines and configure port A for scanning.
+    fn prepare(&self, dev: &mut D) -> Result<(), Error> {
         // Pre-charge capacitors to avoid false positives.
         dev.write_reg(Reg::DirB, 0xFF)?; // port B as input (hi-Z)
         dev.write_reg(Reg::DirA, 0x00)?; // port A temporarily as output
@@ -89,66 +480,267 @@ impl Keypad {
         // Reconfigure port A as input
         dev.write_reg(Reg::DirA, 0xFF)?; // port A as input
         dev.write_reg(Reg::PupA, 0xFF)?; // port A all pull-ups on
+        Ok(())
+    }
 
-        while !self.stop.load(Ordering::SeqCst) {
-            for (scanrow, (pad, row)) in ROWS.iter().enumerate() {
-                let m = !(1u8 << scanrow);
-                dev.write_reg(Reg::DirB, m)?;
-                dev.write_reg(Reg::OutB, m)?;
-                sleep(Duration::from_millis(5));
-
-                let byte = {
-                    let mut buf = [0];
-                    let mut ops = [
-                        I2CMessage::write(&[Reg::InpA as u8]),
-                        I2CMessage::read(&mut buf),
-                    ];
-                    dev.transfer(&mut ops)?;
-                    buf[0]
-                };
+    /// Re-initialize the chip if it lost its configuration. Returns `true` if it did.
+    fn check(&self, dev: &mut D) -> Result<bool, Error> {
+        if dev.read_reg(Reg::IoCon)? == IOCON {
+            return Ok(false);
+        }
+        configure(dev)?;
+        self.prepare(dev)?;
+        self.report(RecoveryEvent::ChipReset);
+        Ok(true)
+    }
+
+    /// Bring the chip back to the scanning state after a bus error.
+    fn recover(&self, dev: &mut D) -> Result<(), Error> {
+        self.check(dev)?;
+        dev.write_reg(Reg::GpIntEnA, 0x00)?;
+        dev.write_reg(Reg::OutB, 0xFF)?;
+        self.prepare(dev)
+    }
 
-                let input = [
-                    byte & (1 << 1) == 0,
-                    byte & (1 << 4) == 0,
-                    byte & (1 << 7) == 0,
-                ];
-                let columns: &mut [bool; 3] = &mut matrix[*pad][*row];
-                for (i, &pressed) in input.iter().enumerate() {
-                    let idx = COLS[i];
-                    match (columns[idx], pressed) {
-                        (false, true) => {
-                            let chr = translate(*pad, *row, idx);
-                            if !self.is_locked(chr) {
-                                columns[idx] = pressed;
-                                if let Some(ref mut cb) = *self.on_pressed.lock().unwrap() {
-                                    cb(chr)
-                                }
-                            }
+    /// Scan all rows once.
+    fn sweep(&self, dev: &mut D, matrix: &mut [[[KeyState; 3]; 4]; 2]) -> Result<(), Error> {
+        let debounce = *self.debounce.lock().unwrap();
+        let keymap = self.keymap.lock().unwrap().clone();
+        let repeat = *self.repeat.lock().unwrap();
+        let allowed = *self.allowed.lock().unwrap();
+        let wiring = self.wiring.lock().unwrap().clone();
+        let mut inputs = [(0, Instant::now()); 8];
+        for (scanrow, input) in inputs.iter_mut().enumerate() {
+            *input = (select_row(dev, scanrow)?, Instant::now());
+            recharge(dev)?;
+        }
+        // Input of a chip reset during the sweep is garbage, start over.
+        if self.check(dev)? {
+            return Ok(());
+        }
+
+        for ((pad, row), &(byte, sampled)) in wiring.rows().iter().zip(&inputs) {
+            let input = wiring.decode(byte);
+            let columns: &mut [KeyState; 3] = &mut matrix[*pad][*row];
+            for (idx, &pressed) in input.iter().enumerate() {
+                let state = &mut columns[idx];
+                let key = keymap.translate(*pad, *row, idx);
+                let chr = key.symbol;
+                let event = |kind| KeyEvent {
+                    symbol: chr,
+                    kind,
+                    timestamp: sampled,
+                    pad: *pad,
+                    row: *row,
+                    column: idx,
+                };
+                if state.debouncer.sample(pressed, &debounce) {
+                    state.debouncer.set(pressed);
+                    if pressed {
+                        *self.last_input.lock().unwrap() = sampled;
+                        let lock = self.get_lock();
+                        // Keys the lock lets through count towards the sequence too.
+                        let unlock =
+                            lock != Lock::Unlocked && self.unlock.lock().unwrap().enter(chr);
+                        if is_locked(lock, &allowed, key) {
+                            state.rejected = true;
+                            self.reject(chr, lock);
+                        } else {
+                            state.pressed_at = Some(sampled);
+                            state.long_pressed = false;
+                            state.next_repeat =
+                                repeat.filter(|_| key.repeat).map(|r| sampled + r.delay);
+                            self.emit(event(KeyEventKind::Pressed));
                         }
-                        (true, false) => {
-                            columns[idx] = pressed;
-                            let chr = translate(*pad, *row, idx);
-                            if let Some(ref mut cb) = *self.on_released.lock().unwrap() {
-                                cb(chr)
-                            }
+                        if unlock {
+                            self.set_lock(Lock::Unlocked);
+                        }
+                    } else if state.rejected {
+                        state.rejected = false;
+                    } else {
+                        state.pressed_at = None;
+                        state.next_repeat = None;
+                        self.emit(event(KeyEventKind::Released));
+                    }
+                } else if let Some(since) = state.pressed_at {
+                    if !state.long_pressed {
+                        let threshold = self.long_press.lock().unwrap().get(&chr).copied();
+                        if threshold.is_some_and(|t| sampled >= since + t) {
+                            state.long_pressed = true;
+                            self.emit(event(KeyEventKind::LongPress));
                         }
-                        _ => {}
+                    }
+                    if let (Some(next), Some(repeat)) = (state.next_repeat, repeat)
+                        && sampled >= next
+                    {
+                        state.next_repeat = Some(sampled + repeat.interval);
+                        self.emit(event(KeyEventKind::Repeat));
                     }
                 }
-                // Re-charge capacitors
-                dev.write_reg(Reg::OutB, 0xFF)?;
-                dev.write_reg(Reg::OutA, 0xFF)?;
-                dev.write_reg(Reg::DirA, 0x00)?;
-                sleep(Duration::from_millis(1));
-                dev.write_reg(Reg::DirA, 0xFF)?;
             }
         }
+
+        self.check_chords();
+        self.check_text();
+        self.check_auto_lock();
+
+        // Nothing to track, sleep until a key is pressed.
+        if matrix.iter().flatten().flatten().all(KeyState::is_idle)
+            && self.irq.lock().unwrap().is_some()
+        {
+            self.idle(dev)?;
+        }
         Ok(())
     }
 
+    /// Wait for the chip interrupt with all rows driven low.
+    fn idle(&self, dev: &mut D) -> Result<(), Error> {
+        let mask = self.wiring.lock().unwrap().column_mask();
+
+        // Any pressed key pulls its column low.
+        dev.write_reg(Reg::OutB, 0x00)?;
+        dev.write_reg(Reg::DirB, 0x00)?;
+
+        // Interrupt when any column differs from HIGH.
+        dev.write_reg(Reg::DefValA, mask)?;
+        dev.write_reg(Reg::IntConA, mask)?;
+        dev.write_reg(Reg::GpIntEnA, mask)?;
+        dev.read_reg(Reg::IntCapA)?; // clear pending interrupt
+
+        // A key may have been pressed before the interrupt was armed.
+        if dev.read_reg(Reg::InpA)? & mask == mask {
+            while !self.stop.load(Ordering::SeqCst) {
+                let woken = match *self.irq.lock().unwrap() {
+                    Some(ref mut irq) => irq.wait(IDLE_POLL)?,
+                    None => true,
+                };
+                // A reset chip will never raise the interrupt, let `sweep` re-initialize it.
+                if woken || dev.read_reg(Reg::IoCon)? != IOCON {
+                    break;
+                }
+                self.check_text();
+                self.check_auto_lock();
+            }
+        }
+
+        dev.write_reg(Reg::GpIntEnA, 0x00)?;
+        dev.read_reg(Reg::IntCapA)?;
+
+        // Release rows and re-charge capacitors
+        dev.write_reg(Reg::OutB, 0xFF)?;
+        dev.write_reg(Reg::DirB, 0xFF)?;
+        dev.write_reg(Reg::OutA, 0xFF)?;
+        dev.write_reg(Reg::DirA, 0x00)?;
+        sleep(Duration::from_millis(1));
+        dev.write_reg(Reg::DirA, 0xFF)?;
+        Ok(())
+    }
+
+    /// Read the state of all keys once, without debouncing, `[pad][row][column]`.
+    ///
+    /// Intended for diagnostics, fails while scanning is running.
+    pub fn raw_scan(&self) -> Result<[[[bool; 3]; 4]; 2], Error> {
+        let mut dev = self.dev.try_lock().map_err(|_| KpError::Busy)?;
+        let wiring = self.wiring.lock().unwrap().clone();
+        let mut matrix = [[[false; 3]; 4]; 2];
+        self.prepare(&mut dev)?;
+        for (scanrow, &(pad, row)) in wiring.rows().iter().enumerate() {
+            let byte = select_row(&mut *dev, scanrow)?;
+            matrix[pad][row] = wiring.decode(byte);
+            recharge(&mut *dev)?;
+        }
+        Ok(matrix)
+    }
+
+    /// Look for shorts between row lines, see `RowCheck`. No key may be pressed.
+    ///
+    /// Intended for diagnostics, fails while scanning is running.
+    pub fn check_rows(&self) -> Result<RowCheck, Error> {
+        let mut dev = self.dev.try_lock().map_err(|_| KpError::Busy)?;
+        let rows = *self.wiring.lock().unwrap().rows();
+        let mut check = RowCheck::default();
+        dev.write_reg(Reg::DirB, 0xFF)?;
+        dev.write_reg(Reg::OutB, 0xFF)?;
+        dev.write_reg(Reg::PupB, 0xFF)?;
+        sleep(Duration::from_millis(5));
+
+        let idle = dev.read_reg(Reg::InpB)?;
+        for (line, &position) in rows.iter().enumerate() {
+            if idle & (1 << line) == 0 {
+                check.grounded.push(position);
+            }
+        }
+        for (line, &driven) in rows.iter().enumerate() {
+            let m = !(1u8 << line);
+            dev.write_reg(Reg::OutB, m)?;
+            dev.write_reg(Reg::DirB, m)?;
+            sleep(Duration::from_millis(5));
+            let byte = dev.read_reg(Reg::InpB)? | !idle;
+            for (other, &position) in rows.iter().enumerate().skip(line + 1) {
+                if byte & (1 << other) == 0 {
+                    check.shorts.push((driven, position));
+                }
+            }
+            dev.write_reg(Reg::DirB, 0xFF)?;
+            dev.write_reg(Reg::OutB, 0xFF)?;
+        }
+
+        dev.write_reg(Reg::PupB, 0x00)?;
+        Ok(check)
+    }
+
+    /// Use the chip interrupt to sleep while no key is pressed. `None` returns to polling.
+    pub fn set_interrupt(&self, irq: Option<Box<dyn Interrupt>>) {
+        *self.irq.lock().unwrap() = irq
+    }
+
     /// Set lock status.
     pub fn set_lock(&self, lock: Lock) {
-        self.lock_state.store(lock, Ordering::Relaxed)
+        let old = self.lock_state.swap(lock, Ordering::Relaxed);
+        if old != lock {
+            self.unlock.lock().unwrap().entered.clear();
+            if lock == Lock::Unlocked {
+                // Give the user the full timeout before locking again.
+                *self.last_input.lock().unwrap() = Instant::now();
+            }
+            self.notify_lock(lock);
+        }
+    }
+
+    /// Lock all keys except `allowed` (switches to `Lock::Custom`).
+    pub fn set_allowed(&self, allowed: KeyMask) {
+        let old = std::mem::replace(&mut *self.allowed.lock().unwrap(), allowed);
+        if old != allowed && self.get_lock() == Lock::Custom {
+            self.notify_lock(Lock::Custom);
+        }
+        self.set_lock(Lock::Custom);
+    }
+
+    /// Lock the keypad after a period of inactivity. `None` disables auto-lock.
+    ///
+    /// The timer runs only while the keypad is `Lock::Unlocked`, counting from
+    /// the last key press or this call, whichever is later.
+    pub fn set_auto_lock(&self, auto_lock: Option<AutoLock>) {
+        if auto_lock.is_some() {
+            *self.last_input.lock().unwrap() = Instant::now();
+        }
+        *self.auto_lock.lock().unwrap() = auto_lock
+    }
+
+    /// Set key sequence that unlocks the keypad when typed while locked.
+    /// An empty sequence disables unlocking from the keypad.
+    ///
+    /// Every key pressed while not `Lock::Unlocked` is counted, keys allowed
+    /// by the lock are still delivered as usual.
+    pub fn set_unlock_sequence(&self, sequence: &[Symbol]) {
+        let mut unlock = self.unlock.lock().unwrap();
+        unlock.sequence = sequence.to_vec();
+        unlock.entered.clear();
+    }
+
+    /// Get keys active in `Lock::Custom` mode.
+    pub fn get_allowed(&self) -> KeyMask {
+        *self.allowed.lock().unwrap()
     }
 
     /// Get lock status.
@@ -156,46 +748,490 @@ impl Keypad {
         self.lock_state.load(Ordering::Relaxed)
     }
 
+    /// Get keys held down, in the order they were pressed.
+    ///
+    /// Only keys whose `Pressed` event was delivered are included, keys rejected
+    /// because of the lock status or suppressed by a chord are not. Empty while
+    /// scanning is not running.
+    pub fn pressed_keys(&self) -> Vec<Symbol> {
+        self.pressed.lock().unwrap().clone()
+    }
+
+    /// Check if a key is held down, see `pressed_keys`.
+    pub fn is_pressed(&self, chr: Symbol) -> bool {
+        self.pressed.lock().unwrap().contains(&chr)
+    }
+
     /// Stop polling thread.
+    ///
+    /// Waits until `scan` returns unless called from the scanning thread
+    /// itself (i.e. from a callback).
     pub fn stop(&self) {
         self.stop.store(true, Ordering::SeqCst);
-        // Wait until the device is released. Try to lock the mutex.
-        let lock = self.dev.lock().unwrap();
-        // Immediatelu release the mutex.
-        drop(lock);
+        let scanner = self.scanner.lock().unwrap();
+        if *scanner == Some(thread::current().id()) {
+            return;
+        }
+        let scanner = self
+            .scanner_done
+            .wait_while(scanner, |scanner| scanner.is_some())
+            .unwrap();
+        drop(scanner);
+        // A scan not registered yet finds the stop request once it gets the device.
+        drop(self.dev.lock().unwrap());
+    }
+
+    /// Set debounce setting.
+    pub fn set_debounce(&self, debounce: Debounce) {
+        *self.debounce.lock().unwrap() = debounce
+    }
+
+    /// Get debounce setting.
+    pub fn get_debounce(&self) -> Debounce {
+        *self.debounce.lock().unwrap()
+    }
+
+    /// Set connection of the key matrix to the chip, applied from the next sweep.
+    pub fn set_wiring(&self, wiring: Wiring) {
+        *self.wiring.lock().unwrap() = wiring
+    }
+
+    /// Get connection of the key matrix to the chip.
+    pub fn get_wiring(&self) -> Wiring {
+        self.wiring.lock().unwrap().clone()
+    }
+
+    /// Set keymap.
+    pub fn set_keymap(&self, keymap: Keymap) {
+        *self.keymap.lock().unwrap() = keymap
+    }
+
+    /// Get keymap.
+    pub fn get_keymap(&self) -> Keymap {
+        self.keymap.lock().unwrap().clone()
+    }
+
+    /// Set long press threshold for the given key. `None` disables long press detection.
+    pub fn set_long_press(&self, chr: Symbol, threshold: Option<Duration>) {
+        let mut long_press = self.long_press.lock().unwrap();
+        match threshold {
+            Some(threshold) => long_press.insert(chr, threshold),
+            None => long_press.remove(&chr),
+        };
+    }
+
+    /// Set auto-repeat setting for keys marked with `repeat` in the keymap.
+    /// `None` disables auto-repeat.
+    pub fn set_repeat(&self, repeat: Option<Repeat>) {
+        *self.repeat.lock().unwrap() = repeat
+    }
+
+    /// Register a key combination, replacing one with the same name.
+    ///
+    /// Keys rejected because of the lock status do not count.
+    pub fn add_chord(&self, chord: Chord) -> Result<(), Error> {
+        if chord.keys.is_empty() {
+            return Err(KpError::InvalidArgument.into());
+        }
+        self.chords.lock().unwrap().insert(chord);
+        Ok(())
+    }
+
+    /// Unregister a key combination. Returns `false` if there is none with the name.
+    pub fn remove_chord(&self, name: &str) -> bool {
+        self.chords.lock().unwrap().remove(name)
+    }
+
+    /// Enable multi-tap text entry fed by key presses, `None` disables it.
+    ///
+    /// Starts with empty text. Key events are delivered as usual.
+    pub fn set_text_entry(&self, multitap: Option<MultiTap>) {
+        *self.text.lock().unwrap() = multitap.map(TextEntry::new)
+    }
+
+    /// Get the committed text, empty if text entry is disabled.
+    pub fn get_text(&self) -> String {
+        match *self.text.lock().unwrap() {
+            Some(ref entry) => entry.text().to_owned(),
+            None => String::new(),
+        }
+    }
+
+    /// Discard the text and the character under composition.
+    pub fn clear_text(&self) {
+        if let Some(ref mut entry) = *self.text.lock().unwrap() {
+            entry.clear();
+        }
+    }
+
+    /// Set bus error recovery policy. `None` makes scanning fail on the first error.
+    pub fn set_recovery(&self, recovery: Option<Recovery>) {
+        *self.recovery.lock().unwrap() = recovery
     }
 
     /// Set `OnPressed` callback.
-    pub fn set_on_pressed(&self, cb: Box<dyn FnMut(Symbol) + Send>) {
-        *self.on_pressed.lock().unwrap() = Some(cb)
+    pub fn set_on_pressed(&self, cb: Callback) {
+        self.on_pressed.set(cb)
     }
 
     /// Set `OnReleased` callback.
-    pub fn set_on_released(&self, cb: Box<dyn FnMut(Symbol) + Send>) {
-        *self.on_released.lock().unwrap() = Some(cb)
+    pub fn set_on_released(&self, cb: Callback) {
+        self.on_released.set(cb)
+    }
+
+    /// Set `OnLongPress` callback.
+    pub fn set_on_long_press(&self, cb: Callback) {
+        self.on_long_press.set(cb)
+    }
+
+    /// Set `OnRepeat` callback.
+    pub fn set_on_repeat(&self, cb: Callback) {
+        self.on_repeat.set(cb)
+    }
+
+    /// Set `OnEvent` callback, invoked for every key event after the kind specific one.
+    pub fn set_on_event(&self, cb: EventCallback) {
+        self.on_event.set(cb)
+    }
+
+    /// Set `OnRecovery` callback.
+    pub fn set_on_recovery(&self, cb: RecoveryCallback) {
+        self.on_recovery.set(cb)
+    }
+
+    /// Set `OnLockChanged` callback, invoked from the thread calling `set_lock`,
+    /// or from the scanning thread on auto-lock and unlock by key sequence.
+    /// The callback may change the lock, the change is reported after it returns.
+    pub fn set_on_lock_changed(&self, cb: LockCallback) {
+        self.on_lock_changed.set(cb)
+    }
+
+    /// Set `OnRejected` callback, invoked for keys pressed while locked.
+    pub fn set_on_rejected(&self, cb: RejectedCallback) {
+        self.on_rejected.set(cb)
+    }
+
+    /// Set `OnChord` callback, invoked once for a key combination held for its time.
+    pub fn set_on_chord(&self, cb: ChordCallback) {
+        self.on_chord.set(cb)
+    }
+
+    /// Set `OnText` callback, invoked for text entry events, see `set_text_entry`.
+    pub fn set_on_text(&self, cb: TextCallback) {
+        self.on_text.set(cb)
+    }
+
+    /// Forward key events to an output backend, e.g. `uinput::Uinput`.
+    pub fn set_output(&self, output: Box<dyn Output>) {
+        self.output.set(output)
+    }
+
+    /// Wait for the next key event.
+    pub fn recv(&self) -> KeyEvent {
+        // The sender lives in `self`, so the channel cannot be disconnected.
+        self.event_rx.lock().unwrap().recv().unwrap()
     }
 
-    /// Check if the keyboard is locked for the given character.
-    fn is_locked(&self, chr: Symbol) -> bool {
-        let lock = self.lock_state.load(Ordering::Relaxed);
-        match lock {
-            Lock::Unlocked => false,
-            Lock::UnlockedPowerOnly if chr.is_power() => false,
-            _ => true,
+    /// Get the next key event if there is one.
+    pub fn try_recv(&self) -> Option<KeyEvent> {
+        self.event_rx.lock().unwrap().try_recv().ok()
+    }
+
+    /// Wait for the next key event at most `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<KeyEvent> {
+        self.event_rx.lock().unwrap().recv_timeout(timeout).ok()
+    }
+
+    /// Time the keypad was initialized.
+    pub fn epoch(&self) -> Instant {
+        self.epoch
+    }
+
+    /// Update key state and deliver the events not suppressed by a chord.
+    fn emit(&self, event: KeyEvent) {
+        let held = {
+            let mut held = self.held.lock().unwrap();
+            match event.kind {
+                KeyEventKind::Pressed => held.insert(event.symbol),
+                KeyEventKind::Released => held.remove(event.symbol),
+                _ => (),
+            }
+            *held
+        };
+
+        let events = self.chords.lock().unwrap().event(event, held);
+        for event in events {
+            {
+                let mut pressed = self.pressed.lock().unwrap();
+                match event.kind {
+                    KeyEventKind::Pressed => pressed.push(event.symbol),
+                    KeyEventKind::Released => {
+                        if let Some(idx) = pressed.iter().position(|&chr| chr == event.symbol) {
+                            pressed.remove(idx);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            self.deliver(event);
+        }
+    }
+
+    /// Deliver event to the queue and the callback.
+    fn deliver(&self, event: KeyEvent) {
+        // Nobody may read the queue, make room by dropping the oldest event.
+        if let Err(TrySendError::Full(event)) = self.event_tx.try_send(event) {
+            // A reader holding the receiver takes an event anyway.
+            if let Ok(rx) = self.event_rx.try_lock() {
+                let _ = rx.try_recv();
+            }
+            let _ = self.event_tx.try_send(event);
+        }
+
+        let cb = match event.kind {
+            KeyEventKind::Pressed => &self.on_pressed,
+            KeyEventKind::Released => &self.on_released,
+            KeyEventKind::LongPress => &self.on_long_press,
+            KeyEventKind::Repeat => &self.on_repeat,
+        };
+        notify(cb, event.symbol);
+        if let Some(cb) = self.on_event.get() {
+            (cb.lock().unwrap())(&event)
+        }
+
+        if let Some(output) = self.output.get() {
+            // Output failure must not stop scanning.
+            let _ = output.lock().unwrap().event(&event);
+        }
+
+        if event.kind == KeyEventKind::Pressed {
+            let events = match *self.text.lock().unwrap() {
+                Some(ref mut entry) => entry.key(event.symbol, event.timestamp),
+                None => return,
+            };
+            self.notify_text(events);
+        }
+    }
+
+    /// Commit the character under composition if its timeout expired.
+    fn check_text(&self) {
+        let event = match *self.text.lock().unwrap() {
+            Some(ref mut entry) => entry.poll(Instant::now()),
+            None => return,
+        };
+        self.notify_text(event);
+    }
+
+    /// Report text entry events.
+    fn notify_text(&self, events: impl IntoIterator<Item = TextEvent>) {
+        for event in events {
+            if let Some(cb) = self.on_text.get() {
+                (cb.lock().unwrap())(event)
+            }
+        }
+    }
+
+    /// Report key combinations held for their time.
+    fn check_chords(&self) {
+        let fired = self.chords.lock().unwrap().poll(Instant::now());
+        for name in fired {
+            if let Some(cb) = self.on_chord.get() {
+                (cb.lock().unwrap())(&name)
+            }
+        }
+    }
+
+    /// Report bus error recovery progress.
+    fn report(&self, event: RecoveryEvent) {
+        if let Some(cb) = self.on_recovery.get() {
+            (cb.lock().unwrap())(event)
+        }
+    }
+
+    /// Lock the keypad if the auto-lock timeout has expired.
+    fn check_auto_lock(&self) {
+        let Some(auto_lock) = *self.auto_lock.lock().unwrap() else {
+            return;
+        };
+        let idle = self.last_input.lock().unwrap().elapsed();
+        if idle >= auto_lock.timeout && self.get_lock() == Lock::Unlocked {
+            self.set_lock(auto_lock.lock);
+        }
+    }
+
+    /// Report lock status change.
+    ///
+    /// Changes made while the callback runs, including by the callback
+    /// itself, are reported in order by the thread already calling it.
+    fn notify_lock(&self, lock: Lock) {
+        let mut notify = self.lock_notify.lock().unwrap();
+        notify.queue.push_back(lock);
+        if notify.busy {
+            return;
+        }
+        notify.busy = true;
+        while let Some(lock) = notify.queue.pop_front() {
+            drop(notify);
+            if let Some(cb) = self.on_lock_changed.get() {
+                (cb.lock().unwrap())(lock)
+            }
+            notify = self.lock_notify.lock().unwrap();
+        }
+        notify.busy = false;
+    }
+
+    /// Report a key pressed while locked.
+    fn reject(&self, chr: Symbol, lock: Lock) {
+        if let Some(cb) = self.on_rejected.get() {
+            (cb.lock().unwrap())(chr, lock)
         }
     }
 }
 
+/// Check if the keyboard is locked for the given key.
+fn is_locked(lock: Lock, allowed: &KeyMask, key: Key) -> bool {
+    match lock {
+        Lock::Unlocked => false,
+        Lock::UnlockedPowerOnly => !key.power,
+        Lock::Custom => !allowed.contains(key.symbol),
+        Lock::Locked => true,
+    }
+}
+
+/// Background scanning thread. Dropping the handle stops the thread.
+pub struct ScanHandle<D = LinuxI2CDevice> {
+    keypad: Arc<Keypad<D>>,
+    thread: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl<D> ScanHandle<D> {
+    /// Check if the thread has exited.
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    /// Wait for the thread to exit and get its result.
+    pub fn join(mut self) -> Result<(), Error> {
+        self.wait()
+    }
+
+    /// Stop the thread and wait for it to exit.
+    pub fn stop(mut self) -> Result<(), Error> {
+        self.keypad.stop.store(true, Ordering::SeqCst);
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<(), Error> {
+        match self.thread.take() {
+            Some(thread) => match thread.join() {
+                Ok(result) => result,
+                Err(_) => Err(anyhow!("keypad thread panicked")),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl<D> Drop for ScanHandle<D> {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.keypad.stop.store(true, Ordering::SeqCst);
+            let _ = self.wait();
+        }
+    }
+}
+
+/// Invoke callback if set.
+fn notify(cb: &Slot<Callback>, chr: Symbol) {
+    if let Some(cb) = cb.get() {
+        (cb.lock().unwrap())(chr)
+    }
+}
+
+/// Drive scan row low and read port A.
+fn select_row<D>(dev: &mut D, scanrow: usize) -> Result<u8, <D as I2CDevice>::Error>
+where
+    D: I2CDevice + for<'a> I2CTransfer<'a, Error = <D as I2CDevice>::Error>,
+{
+    let m = !(1u8 << scanrow);
+    dev.write_reg(Reg::DirB, m)?;
+    dev.write_reg(Reg::OutB, m)?;
+    sleep(Duration::from_millis(5));
+    dev.read_reg(Reg::InpA)
+}
+
+/// Release the row and re-charge capacitors.
+fn recharge<D>(dev: &mut D) -> Result<(), <D as I2CDevice>::Error>
+where
+    D: I2CDevice + for<'a> I2CTransfer<'a, Error = <D as I2CDevice>::Error>,
+{
+    dev.write_reg(Reg::OutB, 0xFF)?;
+    dev.write_reg(Reg::OutA, 0xFF)?;
+    dev.write_reg(Reg::DirA, 0x00)?;
+    sleep(Duration::from_millis(1));
+    dev.write_reg(Reg::DirA, 0xFF)
+}
+
+/// Set MCP23017 to predictable state.
+fn configure<D: I2CDevice>(dev: &mut D) -> Result<(), <D as I2CDevice>::Error> {
+    dev.write(&[0x05, 0b1000_0000])?; // IOCON BANK=1
+    dev.write(&[0x0A, 0b1000_0000])?; // IOCON BANK=1
+    dev.write(&[0x0A, 0b0000_0000])?; // OLATA
+    dev.write(&[0x12, 0b0000_0000])?; // INTCONB (aka 0x05)
+    Ok(())
+}
+
+/// Marks the current thread as the scanning thread while alive.
+struct ScannerGuard<'a, D> {
+    keypad: &'a Keypad<D>,
+}
+
+impl<'a, D> ScannerGuard<'a, D> {
+    fn new(keypad: &'a Keypad<D>) -> Self {
+        *keypad.scanner.lock().unwrap() = Some(thread::current().id());
+        Self { keypad }
+    }
+}
+
+impl<D> Drop for ScannerGuard<'_, D> {
+    fn drop(&mut self) {
+        // Key state is not tracked without scanning.
+        self.keypad.pressed.lock().unwrap().clear();
+        *self.keypad.held.lock().unwrap() = KeyMask::empty();
+        self.keypad.chords.lock().unwrap().reset();
+        // Notify under the lock: a woken `stop` may free the keypad.
+        let mut scanner = self.keypad.scanner.lock().unwrap();
+        *scanner = None;
+        self.keypad.scanner_done.notify_all();
+    }
+}
+
 /// Convenience trait for register-level access.
 trait RegAccess: I2CDevice {
     fn write_reg(&mut self, reg: Reg, val: u8) -> Result<(), <Self as I2CDevice>::Error>;
+    fn read_reg(&mut self, reg: Reg) -> Result<u8, <Self as I2CDevice>::Error>;
 }
 
 impl<D> RegAccess for D
 where
-    D: I2CDevice,
+    D: I2CDevice + for<'a> I2CTransfer<'a, Error = <D as I2CDevice>::Error>,
 {
     fn write_reg(&mut self, reg: Reg, val: u8) -> Result<(), <Self as I2CDevice>::Error> {
         self.write(&[reg as u8, val])
     }
+
+    fn read_reg(&mut self, reg: Reg) -> Result<u8, <Self as I2CDevice>::Error> {
+        let addr = [reg as u8];
+        let mut buf = [0];
+        // Messages borrow `ops` for the whole transfer lifetime, so they must not be dropped.
+        let mut ops = ManuallyDrop::new([
+            <D as I2CTransfer>::Message::write(&addr),
+            <D as I2CTransfer>::Message::read(&mut buf),
+        ]);
+        self.transfer(&mut *ops)?;
+        Ok(buf[0])
+    }
 }
+
+#[cfg(test)]
+mod tests;
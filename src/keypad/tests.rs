@@ -0,0 +1,786 @@
+use std::{
+    ffi::c_char,
+    sync::{Arc, Mutex},
+    thread::{self, sleep},
+    time::Duration,
+};
+
+use super::*;
+use crate::{emulator::Emulator, error::KpError};
+
+/// Time for the scanner to pick up a change of the emulated keys.
+const SETTLE: Duration = Duration::from_millis(150);
+
+/// Symbols of the default layout, `[pad][row][column]` flattened.
+const LAYOUT: &[u8; 24] = b"ABCDEFGHIJKL123456789*0#";
+
+type Log = Arc<Mutex<Vec<(KeyEventKind, char)>>>;
+
+fn settle() {
+    sleep(SETTLE)
+}
+
+/// Record kind and symbol of all delivered key events.
+fn record(keypad: &Keypad<Emulator>) -> Log {
+    let log = Log::default();
+    let l = log.clone();
+    keypad.set_on_event(Box::new(move |ev| {
+        l.lock().unwrap().push((ev.kind, ev.symbol.chr() as char))
+    }));
+    log
+}
+
+fn take(log: &Log) -> Vec<(KeyEventKind, char)> {
+    std::mem::take(&mut *log.lock().unwrap())
+}
+
+fn pressed(chrs: &str) -> Vec<(KeyEventKind, char)> {
+    chrs.chars().map(|c| (KeyEventKind::Pressed, c)).collect()
+}
+
+#[test]
+fn scan_reports_press_and_release() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let log = record(&keypad);
+    let kp = keypad.clone();
+    let scanner = thread::spawn(move || kp.scan());
+
+    emu.press(1, 3, 2);
+    settle();
+    emu.release(1, 3, 2);
+    settle();
+    keypad.stop();
+    scanner.join().unwrap().unwrap();
+
+    assert_eq!(
+        take(&log),
+        [(KeyEventKind::Pressed, '#'), (KeyEventKind::Released, '#')]
+    );
+}
+
+#[test]
+fn default_keymap_translates_all_positions() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let e = events.clone();
+    keypad.set_on_event(Box::new(move |ev| e.lock().unwrap().push(*ev)));
+    let handle = keypad.spawn().unwrap();
+
+    for pad in 0..2 {
+        for row in 0..4 {
+            for column in 0..3 {
+                emu.press(pad, row, column);
+            }
+        }
+    }
+    settle();
+    handle.stop().unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 24);
+    for ev in events.iter() {
+        let expected = LAYOUT[ev.pad * 12 + ev.row * 3 + ev.column];
+        assert_eq!(ev.kind, KeyEventKind::Pressed);
+        assert_eq!(ev.symbol.chr(), expected, "{ev:?}");
+    }
+    let mut symbols: Vec<u8> = events.iter().map(|ev| ev.symbol.chr()).collect();
+    symbols.sort();
+    let mut all = LAYOUT.to_vec();
+    all.sort();
+    assert_eq!(symbols, all);
+}
+
+#[test]
+fn lock_modes_filter_keys() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let log = record(&keypad);
+    let handle = keypad.spawn().unwrap();
+    // 'A', power key 'J' and '1'
+    let keys = [(0, 0, 0), (0, 3, 0), (1, 0, 0)];
+    let tap_all = || {
+        keys.iter().for_each(|&(p, r, c)| emu.press(p, r, c));
+        settle();
+        emu.release_all();
+        settle();
+    };
+
+    keypad.set_lock(Lock::Locked);
+    tap_all();
+    assert_eq!(take(&log), []);
+
+    keypad.set_lock(Lock::UnlockedPowerOnly);
+    tap_all();
+    assert_eq!(
+        take(&log),
+        [(KeyEventKind::Pressed, 'J'), (KeyEventKind::Released, 'J')]
+    );
+
+    keypad.set_lock(Lock::Unlocked);
+    tap_all();
+    let mut log = take(&log);
+    log.sort_by_key(|&(kind, chr)| (kind as u8, chr));
+    let mut expected = pressed("1AJ");
+    expected.extend("1AJ".chars().map(|c| (KeyEventKind::Released, c)));
+    assert_eq!(log, expected);
+
+    handle.stop().unwrap();
+}
+
+#[test]
+fn pressed_keys_in_press_order() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let handle = keypad.spawn().unwrap();
+
+    // '#', 'A' and '5', one at a time
+    for (pad, row, column) in [(1, 3, 2), (0, 0, 0), (1, 1, 1)] {
+        emu.press(pad, row, column);
+        settle();
+    }
+    assert_eq!(keypad.pressed_keys(), symbols("#A5"));
+    assert!(keypad.is_pressed(Symbol::new(b'A')));
+
+    emu.release(0, 0, 0);
+    settle();
+    assert_eq!(keypad.pressed_keys(), symbols("#5"));
+    assert!(!keypad.is_pressed(Symbol::new(b'A')));
+    assert!(keypad.is_pressed(Symbol::new(b'5')));
+
+    handle.stop().unwrap();
+    assert_eq!(keypad.pressed_keys(), []);
+    assert!(!keypad.is_pressed(Symbol::new(b'#')));
+}
+
+#[test]
+fn pressed_keys_skip_suppressed_chord() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    keypad
+        .add_chord(Chord {
+            name: "service".into(),
+            keys: symbols("*#"),
+            hold: Duration::ZERO,
+            suppress: true,
+        })
+        .unwrap();
+    let log = record(&keypad);
+    let handle = keypad.spawn().unwrap();
+
+    // '*' waits for the rest of the chord, '#' completes it.
+    emu.press(1, 3, 0);
+    settle();
+    assert_eq!(keypad.pressed_keys(), []);
+    emu.press(1, 3, 2);
+    settle();
+    assert_eq!(keypad.pressed_keys(), []);
+    assert!(!keypad.is_pressed(Symbol::new(b'*')));
+
+    emu.press(0, 0, 0);
+    settle();
+    assert_eq!(keypad.pressed_keys(), symbols("A"));
+    emu.release(1, 3, 2);
+    settle();
+    assert_eq!(keypad.pressed_keys(), symbols("A"));
+    handle.stop().unwrap();
+
+    assert_eq!(take(&log), pressed("A"));
+}
+
+#[test]
+fn pressed_keys_are_truncated_for_c() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let handle = keypad.spawn().unwrap();
+    for (pad, row, column) in [(1, 3, 2), (0, 0, 0), (1, 1, 1)] {
+        emu.press(pad, row, column);
+        settle();
+    }
+    let pressed: Vec<u8> = keypad.pressed_keys().iter().map(|chr| chr.chr()).collect();
+    handle.stop().unwrap();
+
+    let store = |len: usize| {
+        let mut buf = [-1 as c_char; 5];
+        let result = unsafe { crate::store_chars(&pressed, buf.as_mut_ptr(), len) };
+        assert_eq!(result, KpError::Ok);
+        buf.map(|chr| chr as u8)
+    };
+    assert_eq!(store(5), *b"#A5\0\xff");
+    assert_eq!(store(4), *b"#A5\0\xff");
+    assert_eq!(store(3), *b"#A\0\xff\xff");
+    assert_eq!(store(1), *b"\0\xff\xff\xff\xff");
+
+    let mut buf = [0; 4];
+    let result = unsafe { crate::store_chars(&pressed, buf.as_mut_ptr(), 0) };
+    assert_eq!(result, KpError::InvalidArgument);
+    let result = unsafe { crate::store_chars(&pressed, std::ptr::null_mut(), 4) };
+    assert_eq!(result, KpError::InvalidArgument);
+}
+
+#[test]
+fn short_bounce_is_ignored() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    keypad.set_debounce(Debounce {
+        press: 4,
+        release: 2,
+    });
+    let log = record(&keypad);
+    let handle = keypad.spawn().unwrap();
+
+    // Shorter than four samples of a press.
+    emu.press(0, 0, 0);
+    sleep(Duration::from_millis(60));
+    emu.release(0, 0, 0);
+    settle();
+    assert_eq!(take(&log), []);
+
+    emu.press(0, 0, 0);
+    sleep(Duration::from_millis(400));
+    assert_eq!(take(&log), pressed("A"));
+
+    // Shorter than two samples of a release.
+    emu.release(0, 0, 0);
+    sleep(Duration::from_millis(30));
+    emu.press(0, 0, 0);
+    settle();
+    assert_eq!(take(&log), []);
+
+    emu.release(0, 0, 0);
+    settle();
+    handle.stop().unwrap();
+    assert_eq!(take(&log), [(KeyEventKind::Released, 'A')]);
+}
+
+type Events = Arc<Mutex<Vec<KeyEvent>>>;
+
+/// Record all delivered key events with their details.
+fn record_events(keypad: &Keypad<Emulator>) -> Events {
+    let events = Events::default();
+    let e = events.clone();
+    keypad.set_on_event(Box::new(move |ev| e.lock().unwrap().push(*ev)));
+    events
+}
+
+/// Events of key `chr` with their kind.
+fn of(events: &Events, chr: u8) -> Vec<(KeyEventKind, Instant)> {
+    let events = events.lock().unwrap();
+    events
+        .iter()
+        .filter(|ev| ev.symbol.chr() == chr)
+        .map(|ev| (ev.kind, ev.timestamp))
+        .collect()
+}
+
+#[test]
+fn long_press_fires_once_while_held() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let threshold = Duration::from_millis(300);
+    keypad.set_long_press(Symbol::new(b'A'), Some(threshold));
+    let events = record_events(&keypad);
+    let handle = keypad.spawn().unwrap();
+
+    // Released before the threshold.
+    emu.press(0, 0, 0);
+    sleep(Duration::from_millis(150));
+    emu.release(0, 0, 0);
+    sleep(Duration::from_millis(300));
+    // Held well past the threshold, 'B' has none.
+    emu.press(0, 0, 0);
+    emu.press(0, 0, 1);
+    sleep(Duration::from_millis(900));
+    emu.release_all();
+    settle();
+    handle.stop().unwrap();
+
+    let a = of(&events, b'A');
+    let kinds: Vec<KeyEventKind> = a.iter().map(|&(kind, _)| kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            KeyEventKind::Pressed,
+            KeyEventKind::Released,
+            KeyEventKind::Pressed,
+            KeyEventKind::LongPress,
+            KeyEventKind::Released
+        ]
+    );
+    let (pressed_at, long_pressed_at) = (a[2].1, a[3].1);
+    assert!(long_pressed_at >= pressed_at + threshold);
+    assert!(long_pressed_at < pressed_at + threshold + SETTLE);
+    assert_eq!(of(&events, b'B').len(), 2);
+}
+
+/// Default keymap with the `repeat` flag on `repeat` keys.
+fn keymap_with_repeat(repeat: &[u8]) -> Keymap {
+    let mut text = String::new();
+    for (n, &chr) in LAYOUT.iter().enumerate() {
+        let (pad, row, column) = (n / 12, n % 12 / 3, n % 3);
+        let power = if chr == b'J' { " power" } else { "" };
+        let flag = if repeat.contains(&chr) { " repeat" } else { "" };
+        text += &format!("{pad} {row} {column} {}{power}{flag}\n", chr as char);
+    }
+    Keymap::parse(&text).unwrap()
+}
+
+#[test]
+fn repeat_only_keys_with_flag() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    keypad.set_keymap(keymap_with_repeat(b"B"));
+    let (delay, interval) = (Duration::from_millis(300), Duration::from_millis(150));
+    keypad.set_repeat(Some(Repeat { delay, interval }));
+    let events = record_events(&keypad);
+    let handle = keypad.spawn().unwrap();
+
+    emu.press(0, 0, 0);
+    emu.press(0, 0, 1);
+    sleep(Duration::from_millis(1000));
+    emu.release_all();
+    sleep(Duration::from_millis(400));
+    handle.stop().unwrap();
+
+    let a: Vec<KeyEventKind> = of(&events, b'A').iter().map(|&(kind, _)| kind).collect();
+    assert_eq!(a, [KeyEventKind::Pressed, KeyEventKind::Released]);
+
+    let b = of(&events, b'B');
+    assert_eq!(b.first().unwrap().0, KeyEventKind::Pressed);
+    assert_eq!(b.last().unwrap().0, KeyEventKind::Released);
+    let repeats = &b[1..b.len() - 1];
+    assert!(repeats.len() >= 3, "{b:?}");
+    assert!(
+        repeats
+            .iter()
+            .all(|&(kind, _)| kind == KeyEventKind::Repeat)
+    );
+    let mut last = b[0].1;
+    for (n, &(_, at)) in repeats.iter().enumerate() {
+        let expected = if n == 0 { delay } else { interval };
+        assert!(
+            at >= last + expected && at < last + expected + SETTLE,
+            "{b:?}"
+        );
+        last = at;
+    }
+}
+
+#[test]
+fn full_queue_drops_oldest_event() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Builder::new().event_queue(2).build(emu.clone()).unwrap());
+    let handle = keypad.spawn().unwrap();
+
+    for column in 0..3 {
+        emu.press(0, 0, column);
+        settle();
+    }
+    handle.stop().unwrap();
+
+    let symbols: Vec<u8> = std::iter::from_fn(|| keypad.try_recv())
+        .map(|ev| ev.symbol.chr())
+        .collect();
+    assert_eq!(symbols, b"BC");
+}
+
+#[test]
+fn stop_from_callback_ends_scanning() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let kp = keypad.clone();
+    keypad.set_on_pressed(Box::new(move |_| kp.stop()));
+    let kp = keypad.clone();
+    let scanner = thread::spawn(move || kp.scan());
+
+    emu.press(0, 0, 0);
+    settle();
+    assert!(scanner.is_finished());
+    scanner.join().unwrap().unwrap();
+}
+
+#[test]
+fn stop_waits_for_device() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu).unwrap());
+    let dev = keypad.dev.lock().unwrap();
+    let kp = keypad.clone();
+    let scanner = thread::spawn(move || kp.scan());
+    settle();
+
+    // The scanner waits for the device, so does `stop`.
+    let kp = keypad.clone();
+    let stopper = thread::spawn(move || kp.stop());
+    settle();
+    assert!(!stopper.is_finished());
+    drop(dev);
+    stopper.join().unwrap();
+    assert!(keypad.raw_scan().is_ok());
+    scanner.join().unwrap().unwrap();
+
+    for _ in 0..10 {
+        let kp = keypad.clone();
+        let scanner = thread::spawn(move || kp.scan());
+        sleep(Duration::from_millis(20));
+        keypad.stop();
+        assert!(keypad.raw_scan().is_ok());
+        scanner.join().unwrap().unwrap();
+    }
+}
+
+#[test]
+fn callback_can_replace_itself() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (kp, l) = (keypad.clone(), log.clone());
+    keypad.set_on_pressed(Box::new(move |chr| {
+        l.lock().unwrap().push(("first", chr.chr()));
+        let l = l.clone();
+        kp.set_on_pressed(Box::new(move |chr| {
+            l.lock().unwrap().push(("second", chr.chr()))
+        }));
+    }));
+    let handle = keypad.spawn().unwrap();
+
+    emu.press(0, 0, 0);
+    settle();
+    emu.press(0, 0, 1);
+    settle();
+    handle.stop().unwrap();
+
+    assert_eq!(*log.lock().unwrap(), [("first", b'A'), ("second", b'B')]);
+}
+
+#[test]
+fn callback_can_set_lock() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let kp = keypad.clone();
+    keypad.set_on_pressed(Box::new(move |_| kp.set_lock(Lock::Locked)));
+    let log = record(&keypad);
+    let handle = keypad.spawn().unwrap();
+
+    emu.press(0, 0, 0);
+    settle();
+    emu.press(0, 0, 1);
+    settle();
+    handle.stop().unwrap();
+
+    assert_eq!(keypad.get_lock(), Lock::Locked);
+    assert_eq!(take(&log), pressed("A"));
+}
+
+#[test]
+fn lock_callback_can_set_lock() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu).unwrap());
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let (kp, s) = (keypad.clone(), seen.clone());
+    keypad.set_on_lock_changed(Box::new(move |lock| {
+        s.lock().unwrap().push(lock);
+        if lock == Lock::Locked {
+            kp.set_lock(Lock::UnlockedPowerOnly);
+            kp.set_allowed(KeyMask::empty());
+        }
+    }));
+
+    keypad.set_lock(Lock::Locked);
+    assert_eq!(keypad.get_lock(), Lock::Custom);
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [Lock::Locked, Lock::UnlockedPowerOnly, Lock::Custom]
+    );
+}
+
+fn symbols(chrs: &str) -> Vec<Symbol> {
+    chrs.bytes().map(Symbol::new).collect()
+}
+
+#[test]
+fn unlock_sequence_matches_last_keys() {
+    let mut unlock = Unlock::default();
+    assert!(!unlock.enter(Symbol::new(b'1')));
+
+    unlock.sequence = symbols("12");
+    let entered: Vec<bool> = symbols("1112212")
+        .into_iter()
+        .map(|chr| unlock.enter(chr))
+        .collect();
+    assert_eq!(entered, [false, false, false, true, false, false, true]);
+}
+
+#[test]
+fn auto_lock_and_unlock_by_sequence() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let log = record(&keypad);
+    let locks = Arc::new(Mutex::new(Vec::new()));
+    let l = locks.clone();
+    keypad.set_on_lock_changed(Box::new(move |lock| l.lock().unwrap().push(lock)));
+    keypad.set_auto_lock(Some(AutoLock {
+        timeout: Duration::from_millis(400),
+        lock: Lock::Locked,
+    }));
+    keypad.set_unlock_sequence(&symbols("**#"));
+    let handle = keypad.spawn().unwrap();
+    let tap = |row, column| {
+        emu.press(1, row, column);
+        settle();
+        emu.release(1, row, column);
+        settle();
+    };
+
+    tap(0, 0);
+    assert_eq!(keypad.get_lock(), Lock::Unlocked);
+    sleep(Duration::from_millis(600));
+    assert_eq!(keypad.get_lock(), Lock::Locked);
+
+    // '*' '*' '*' '#'
+    [(3, 0), (3, 0), (3, 0), (3, 2)]
+        .into_iter()
+        .for_each(|(r, c)| tap(r, c));
+    assert_eq!(keypad.get_lock(), Lock::Unlocked);
+    tap(0, 1);
+    handle.stop().unwrap();
+
+    let mut expected = pressed("1");
+    expected.push((KeyEventKind::Released, '1'));
+    expected.extend([(KeyEventKind::Pressed, '2'), (KeyEventKind::Released, '2')]);
+    assert_eq!(take(&log), expected);
+    assert_eq!(*locks.lock().unwrap(), [Lock::Locked, Lock::Unlocked]);
+}
+
+#[test]
+fn auto_lock_counts_from_enabling() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu).unwrap());
+    let handle = keypad.spawn().unwrap();
+    sleep(Duration::from_millis(600));
+
+    keypad.set_auto_lock(Some(AutoLock {
+        timeout: Duration::from_millis(500),
+        lock: Lock::Locked,
+    }));
+    sleep(Duration::from_millis(300));
+    assert_eq!(keypad.get_lock(), Lock::Unlocked);
+    sleep(Duration::from_millis(400));
+    assert_eq!(keypad.get_lock(), Lock::Locked);
+    handle.stop().unwrap();
+}
+
+#[test]
+fn unlock_sequence_counts_allowed_keys() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let log = record(&keypad);
+    keypad.set_unlock_sequence(&symbols("1J"));
+    keypad.set_lock(Lock::UnlockedPowerOnly);
+    let handle = keypad.spawn().unwrap();
+
+    // '1' is rejected, power key 'J' is delivered and completes the sequence.
+    for (pad, row) in [(1, 0), (0, 3)] {
+        emu.press(pad, row, 0);
+        settle();
+        emu.release(pad, row, 0);
+        settle();
+    }
+    handle.stop().unwrap();
+
+    assert_eq!(keypad.get_lock(), Lock::Unlocked);
+    assert_eq!(
+        take(&log),
+        [(KeyEventKind::Pressed, 'J'), (KeyEventKind::Released, 'J')]
+    );
+}
+
+#[test]
+fn idle_wakes_up_on_interrupt() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(
+        Builder::new()
+            .interrupt(emu.interrupt())
+            .build(emu.clone())
+            .unwrap(),
+    );
+    let mask = Wiring::default().column_mask();
+    let reports = record_recovery(&keypad);
+    let log = record(&keypad);
+    let handle = keypad.spawn().unwrap();
+    let idle = Duration::from_millis(350);
+    let is_idle =
+        || emu.register(Reg::GpIntEnA as u8) == mask && emu.register(Reg::DirB as u8) == 0x00;
+
+    for (pad, row, column) in [(0, 0, 0), (1, 3, 2), (0, 2, 1)] {
+        sleep(idle);
+        assert!(is_idle());
+        emu.press(pad, row, column);
+        settle();
+        // Scanning while the key is held.
+        assert_eq!(emu.register(Reg::GpIntEnA as u8), 0x00);
+        emu.release(pad, row, column);
+        settle();
+    }
+
+    // A reset chip is noticed without any key pressed.
+    sleep(idle);
+    emu.power_cycle();
+    sleep(idle);
+    assert!(is_idle());
+    assert_eq!(emu.register(Reg::IoCon as u8), IOCON);
+    emu.press(1, 0, 0);
+    settle();
+    handle.stop().unwrap();
+
+    let mut expected = Vec::new();
+    for chr in "A#H".chars() {
+        expected.extend([(KeyEventKind::Pressed, chr), (KeyEventKind::Released, chr)]);
+    }
+    expected.push((KeyEventKind::Pressed, '1'));
+    assert_eq!(take(&log), expected);
+    assert_eq!(*reports.lock().unwrap(), [RecoveryEvent::ChipReset]);
+}
+
+#[test]
+fn check_rows_finds_wiring_faults() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    assert_eq!(keypad.check_rows().unwrap(), RowCheck::default());
+    // Pull-ups are only on for the check.
+    assert_eq!(emu.register(Reg::PupB as u8), 0x00);
+
+    emu.short_rows(1, 4);
+    emu.short_rows(4, 7);
+    emu.ground_row(6);
+    let rows = Wiring::default().rows().to_owned();
+    assert_eq!(
+        keypad.check_rows().unwrap(),
+        RowCheck {
+            shorts: vec![(rows[1], rows[4]), (rows[1], rows[7]), (rows[4], rows[7])],
+            grounded: vec![rows[6]],
+        }
+    );
+
+    let handle = keypad.spawn().unwrap();
+    settle();
+    let e = keypad.check_rows().unwrap_err();
+    assert_eq!(KpError::of(&e), KpError::Busy);
+    handle.stop().unwrap();
+}
+
+#[test]
+fn builder_options() {
+    let builder = Builder::new()
+        .option("--device", "/dev/i2c-7")
+        .and_then(|b| b.option("--address", "0x21"))
+        .unwrap();
+    assert_eq!(builder.device, PathBuf::from("/dev/i2c-7"));
+    assert_eq!(builder.address, 0x21);
+    assert_eq!(builder.option("--address", "33").unwrap().address, 33);
+
+    let error = |name, value| match Builder::new().option(name, value) {
+        Ok(_) => panic!("{name} {value} accepted"),
+        Err(e) => format!("{e:#}"),
+    };
+    assert_eq!(
+        error("--address", "0x"),
+        "invalid address `0x`: cannot parse integer from empty string"
+    );
+    assert_eq!(
+        error("--address", "0x10000"),
+        "invalid address `0x10000`: number too large to fit in target type"
+    );
+    assert!(error("--wiring", "/nonexistent").starts_with("cannot load wiring: "));
+    assert_eq!(error("--socket", "x"), "unknown option `--socket`");
+}
+
+type Reports = Arc<Mutex<Vec<RecoveryEvent>>>;
+
+fn record_recovery(keypad: &Keypad<Emulator>) -> Reports {
+    let reports = Reports::default();
+    let r = reports.clone();
+    keypad.set_on_recovery(Box::new(move |ev| r.lock().unwrap().push(ev)));
+    reports
+}
+
+#[test]
+fn bus_errors_are_retried() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let reports = record_recovery(&keypad);
+    let log = record(&keypad);
+    let handle = keypad.spawn().unwrap();
+    settle();
+
+    emu.glitch(3);
+    sleep(Duration::from_millis(600));
+    emu.press(1, 0, 0);
+    settle();
+    assert!(!handle.is_finished());
+    assert_eq!(
+        *reports.lock().unwrap(),
+        [
+            RecoveryEvent::BusError,
+            RecoveryEvent::BusError,
+            RecoveryEvent::BusError,
+            RecoveryEvent::Recovered
+        ]
+    );
+    assert_eq!(take(&log), pressed("1"));
+
+    // Give up after the configured number of retries.
+    keypad.set_recovery(Some(Recovery {
+        retries: 2,
+        delay: Duration::from_millis(10),
+    }));
+    emu.glitch(u32::MAX);
+    let e = handle.join().unwrap_err();
+    assert_eq!(KpError::of(&e), KpError::Nack);
+}
+
+#[test]
+fn reset_chip_is_reconfigured() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(Keypad::new(emu.clone()).unwrap());
+    let reports = record_recovery(&keypad);
+    let log = record(&keypad);
+    let handle = keypad.spawn().unwrap();
+    settle();
+
+    emu.power_cycle();
+    settle();
+    assert_eq!(emu.register(Reg::IoCon as u8), IOCON);
+    emu.press(0, 2, 1);
+    settle();
+    handle.stop().unwrap();
+
+    assert!(reports.lock().unwrap().contains(&RecoveryEvent::ChipReset));
+    assert_eq!(take(&log), pressed("H"));
+}
+
+/// Interrupt line that cannot be read.
+struct BrokenInterrupt;
+
+impl Interrupt for BrokenInterrupt {
+    fn wait(&mut self, _timeout: Duration) -> Result<bool, Error> {
+        Err(anyhow!("GPIO line gone"))
+    }
+}
+
+#[test]
+fn interrupt_errors_are_not_retried() {
+    let emu = Emulator::new();
+    let keypad = Arc::new(
+        Builder::new()
+            .interrupt(BrokenInterrupt)
+            .build(emu.clone())
+            .unwrap(),
+    );
+    let reports = record_recovery(&keypad);
+    let handle = keypad.spawn().unwrap();
+
+    let e = handle.join().unwrap_err();
+    assert_eq!(e.to_string(), "GPIO line gone");
+    assert_eq!(*reports.lock().unwrap(), []);
+}
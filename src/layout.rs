@@ -1,3 +1,8 @@
+use anyhow::{Error, bail};
+use std::{fs, path::Path};
+
+use super::parse::{self, Fields};
+
 #[rustfmt::skip]
 const LAYOUT: [[[u8; 3]; 4]; 2] = [
     // Left keypad
@@ -17,21 +22,236 @@ const LAYOUT: [[[u8; 3]; 4]; 2] = [
     ],
 ];
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+const POWER: u8 = b'J';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Symbol(u8);
 
 impl Symbol {
+    #[inline]
+    pub const fn new(chr: u8) -> Self {
+        Self(chr)
+    }
+
     #[inline]
     pub fn chr(&self) -> u8 {
         self.0
     }
+}
+
+/// Set of key symbols (ASCII only).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyMask(u128);
+
+impl KeyMask {
+    /// Mask with no keys.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
 
+    /// Mask with the given symbols. Non-ASCII symbols are ignored.
+    pub fn from_symbols(chrs: &[u8]) -> Self {
+        chrs.iter().map(|&chr| Symbol(chr)).collect()
+    }
+
+    /// Add a key to the mask.
+    pub fn insert(&mut self, chr: Symbol) {
+        self.0 |= Self::bit(chr)
+    }
+
+    /// Remove a key from the mask.
+    pub fn remove(&mut self, chr: Symbol) {
+        self.0 &= !Self::bit(chr)
+    }
+
+    /// Add all keys of another mask.
+    pub fn insert_all(&mut self, other: &KeyMask) {
+        self.0 |= other.0
+    }
+
+    /// Check if the key is in the mask.
+    pub fn contains(&self, chr: Symbol) -> bool {
+        self.0 & Self::bit(chr) != 0
+    }
+
+    /// Check if all keys of another mask are in the mask.
+    pub fn contains_all(&self, other: &KeyMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn bit(chr: Symbol) -> u128 {
+        1u128.checked_shl(chr.0.into()).unwrap_or(0)
+    }
+}
+
+impl FromIterator<Symbol> for KeyMask {
+    fn from_iter<I: IntoIterator<Item = Symbol>>(iter: I) -> Self {
+        let mut mask = Self::empty();
+        iter.into_iter().for_each(|chr| mask.insert(chr));
+        mask
+    }
+}
+
+/// Key assigned to a matrix position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub symbol: Symbol,
+    /// Key stays active in `Lock::UnlockedPowerOnly` mode.
+    pub power: bool,
+    /// Key generates auto-repeat events while held.
+    pub repeat: bool,
+}
+
+/// Assignment of keys to matrix positions, `keys[pad][row][column]`.
+///
+/// Text representation has one key per line: pad, row and column numbers
+/// (counted from 0), the symbol character and optional flags. Empty lines and
+/// lines starting with `#` are ignored.
+///
+/// ```text
+/// # pad row column symbol flags
+/// 0 3 0 J power
+/// 0 0 1 B repeat
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [[[Key; 3]; 4]; 2],
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let keys = LAYOUT.map(|pad| {
+            pad.map(|row| {
+                row.map(|chr| Key {
+                    symbol: Symbol(chr),
+                    power: chr == POWER,
+                    repeat: false,
+                })
+            })
+        });
+        Self { keys }
+    }
+}
+
+impl Keymap {
+    /// Load keymap from a file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text)
+    }
+
+    /// Parse keymap from its text representation.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut keys: [[[Option<Key>; 3]; 4]; 2] = Default::default();
+
+        for (n, line) in parse::lines(text) {
+            let mut fields = Fields::new(n, line);
+            let pad = fields.index("pad", 2)?;
+            let row = fields.index("row", 4)?;
+            let column = fields.index("column", 3)?;
+            let symbol = fields.symbol()?;
+
+            let mut key = Key {
+                symbol,
+                power: false,
+                repeat: false,
+            };
+            for flag in fields {
+                match flag {
+                    "power" => key.power = true,
+                    "repeat" => key.repeat = true,
+                    _ => bail!("line {n}: unknown flag `{flag}`"),
+                }
+            }
+
+            if keys[pad][row][column].is_some() {
+                bail!("line {n}: key {pad} {row} {column} defined twice");
+            }
+            if keys
+                .iter()
+                .flatten()
+                .flatten()
+                .flatten()
+                .any(|k| k.symbol == symbol)
+            {
+                bail!("line {n}: symbol `{}` assigned twice", symbol.0 as char);
+            }
+            keys[pad][row][column] = Some(key);
+        }
+
+        let mut keymap = Self::default();
+        for (pad, rows) in keys.iter().enumerate() {
+            for (row, columns) in rows.iter().enumerate() {
+                for (column, key) in columns.iter().enumerate() {
+                    match key {
+                        Some(key) => keymap.keys[pad][row][column] = *key,
+                        None => bail!("key {pad} {row} {column} is not defined"),
+                    }
+                }
+            }
+        }
+        Ok(keymap)
+    }
+
+    /// Get key at the given matrix position.
     #[inline]
-    pub fn is_power(&self) -> bool {
-        self.chr() == b'J'
+    pub fn translate(&self, pad: usize, row: usize, column: usize) -> Key {
+        self.keys[pad][row][column]
     }
 }
 
-pub fn translate(pad: usize, row: usize, column: usize) -> Symbol {
-    Symbol(LAYOUT[pad][row][column])
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text representation of `keymap`.
+    fn text(keymap: &Keymap) -> String {
+        let mut text = String::from("# pad row column symbol flags\n");
+        for (pad, rows) in keymap.keys.iter().enumerate() {
+            for (row, columns) in rows.iter().enumerate() {
+                for (column, key) in columns.iter().enumerate() {
+                    let chr = key.symbol.0 as char;
+                    let power = if key.power { " power" } else { "" };
+                    let repeat = if key.repeat { " repeat" } else { "" };
+                    text += &format!("{pad} {row} {column} {chr}{power}{repeat}\n");
+                }
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn parse_keymap() {
+        let mut keymap = Keymap::default();
+        assert_eq!(Keymap::parse(&text(&keymap)).unwrap(), keymap);
+
+        keymap.keys[0][0][1].repeat = true;
+        keymap.keys[1][3][2].power = true;
+        let parsed = Keymap::parse(&format!("\n  {}", text(&keymap))).unwrap();
+        assert_eq!(parsed, keymap);
+        assert!(parsed.translate(0, 0, 1).repeat);
+        assert!(parsed.translate(1, 3, 2).power);
+    }
+
+    #[test]
+    fn parse_keymap_errors() {
+        let error = |text: &str| Keymap::parse(text).unwrap_err().to_string();
+
+        let missing = text(&Keymap::default()).replace("0 0 0 A\n", "");
+        assert_eq!(error(&missing), "key 0 0 0 is not defined");
+        assert_eq!(error("0 0 0 A\n0 0 0 B"), "line 2: key 0 0 0 defined twice");
+        assert_eq!(
+            error("0 0 0 A\n0 0 1 A"),
+            "line 2: symbol `A` assigned twice"
+        );
+        assert_eq!(error("0 0 3 A"), "line 1: invalid column `3`");
+        assert_eq!(error("2 0 0 A"), "line 1: invalid pad `2`");
+        assert_eq!(error("0 0"), "line 1: missing column");
+        assert_eq!(error("0 0 0"), "line 1: missing symbol");
+        assert_eq!(
+            error("0 0 0 AB"),
+            "line 1: symbol must be a single printable ASCII character"
+        );
+        assert_eq!(error("0 0 0 A bogus"), "line 1: unknown flag `bogus`");
+    }
 }
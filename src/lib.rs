@@ -1,113 +1,969 @@
-mod keypad;
-mod layout;
+// The `keypad_*` functions are the C API. They share one contract, documented
+// in `c++/ckeypad` rather than on each function: `kp` comes from `keypad_new`
+// and is not yet deleted, and string arguments are valid NUL-terminated strings.
 
+pub mod chord;
+pub mod daemon;
+pub mod debounce;
+#[cfg(any(test, feature = "emulator"))]
+pub mod emulator;
+pub mod error;
+pub mod event;
+pub mod interrupt;
+pub mod keypad;
+pub mod layout;
+mod parse;
+pub mod text;
+pub mod uinput;
+pub mod wiring;
+
+use anyhow::Error;
 use atomic_enum::atomic_enum;
-use std::ffi::{c_int, c_char};
-use stdint::uint32_t;
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, c_char, c_int, c_void},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use stdint::{uint8_t, uint16_t, uint32_t, uint64_t};
 
-use keypad::Keypad as KeypadDriver;
-use layout::Symbol;
+use chord::Chord;
+use debounce::Debounce;
+use error::KpError;
+use event::{KeyEvent, KeyEventKind, RecoveryEvent};
+use interrupt::GpioInterrupt;
+use keypad::{AutoLock, Builder, Callback, Keypad as KeypadDriver, Recovery, Repeat, ScanHandle};
+use layout::{KeyMask, Keymap, Symbol};
+use text::{MultiTap, TextEvent, TextEventKind};
+use uinput::{Keycodes, Uinput};
+use wiring::Wiring;
 
 #[repr(C)]
 #[atomic_enum]
+#[derive(PartialEq, Eq)]
 pub enum Lock {
     Locked = 0,
     Unlocked = 1,
     UnlockedPowerOnly = 2,
+    /// Only keys in the allowed mask are active, see `Keypad::set_allowed`.
+    Custom = 3,
 }
 
 pub struct Keypad {
-    driver: Option<KeypadDriver>,
+    driver: Option<Arc<KeypadDriver>>,
+    thread: Option<ScanHandle>,
+}
+
+thread_local! {
+    /// Message of the last error on this thread.
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// Remember error message and get its code.
+fn fail(e: impl Into<Error>) -> KpError {
+    let e = e.into();
+    let msg = CString::new(format!("{e:#}")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = msg);
+    KpError::of(&e)
+}
+
+/// Like `fail` for loading a file: anything but an I/O error is a syntax error in it.
+fn fail_parse(e: Error) -> KpError {
+    match fail(e) {
+        KpError::Unknown => KpError::InvalidArgument,
+        code => code,
+    }
 }
 
+/// Get human-readable message of the last failed call on the calling thread.
+/// The pointer stays valid until the next failed call on the same thread.
 #[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn keypad_new() -> *mut Keypad {
-    let keypad = Keypad { driver: None };
+    let keypad = Keypad {
+        driver: None,
+        thread: None,
+    };
     Box::into_raw(Box::new(keypad))
 }
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn keypad_delete(kp: *mut Keypad) {
-    unsafe {
-        let kp = Box::from_raw(kp);
-        if let Some(drv) = kp.driver {
-            drv.stop();
-        }
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_delete(kp: *mut Keypad) -> KpError {
+    let kp = unsafe { Box::from_raw(kp) };
+    let mut result = KpError::Ok;
+    if let Some(thread) = kp.thread
+        && let Err(e) = thread.stop()
+    {
+        result = fail(e);
     }
+    if let Some(drv) = kp.driver {
+        drv.stop();
+    }
+    result
 }
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn keypad_init(kp: *mut Keypad) -> c_int {
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_init(kp: *mut Keypad) -> KpError {
     let kp = unsafe { &mut *kp };
-    match KeypadDriver::open() {
+    init(kp, KeypadDriver::builder())
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_init_ex(kp: *mut Keypad, path: *const c_char, addr: uint16_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let path = unsafe { CStr::from_ptr(path) };
+    let path = match path.to_str() {
+        Ok(path) => path,
+        Err(e) => return fail(e),
+    };
+    init(kp, KeypadDriver::builder().device(path).address(addr))
+}
+
+fn init(kp: &mut Keypad, builder: Builder) -> KpError {
+    match builder.open() {
         Ok(drv) => {
-            kp.driver = Some(drv);
-            1
+            kp.driver = Some(Arc::new(drv));
+            KpError::Ok
         }
+        Err(e) => fail(e),
+    }
+}
 
-        Err(e) => {
-            eprintln!("Keypad open error: {e}");
-            0
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_run(kp: *mut Keypad) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    match drv.scan() {
+        Ok(()) => KpError::Ok,
+        Err(e) => fail(e),
+    }
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_start(kp: *mut Keypad) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    if kp.thread.as_ref().is_some_and(|thread| !thread.is_finished()) {
+        return fail(KpError::AlreadyRunning);
+    }
+    match drv.spawn() {
+        Ok(thread) => {
+            kp.thread = Some(thread);
+            KpError::Ok
         }
+        Err(e) => fail(e),
+    }
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_join(kp: *mut Keypad) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(thread) = kp.thread.take() else {
+        return fail(KpError::NotRunning);
+    };
+    match thread.join() {
+        Ok(()) => KpError::Ok,
+        Err(e) => fail(e),
     }
 }
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn keypad_run(kp: *mut Keypad) {
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_interrupt(kp: *mut Keypad, chip: *const c_char, line: uint32_t) -> KpError {
     let kp = unsafe { &mut *kp };
-    if let Some(ref mut drv) = kp.driver {
-        if let Err(e) = drv.scan() {
-            eprintln!("Keypad scan error: {e}");
+    let chip = unsafe { CStr::from_ptr(chip) };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let irq = chip
+        .to_str()
+        .map_err(Into::into)
+        .and_then(|chip| GpioInterrupt::new(chip, line));
+    match irq {
+        Ok(irq) => {
+            drv.set_interrupt(Some(Box::new(irq)));
+            KpError::Ok
         }
+        Err(e) => fail(e),
+    }
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_lock(kp: *mut Keypad, lock: Lock) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    drv.set_lock(lock);
+    KpError::Ok
+}
+
+/// Lock all keys except the characters of `chars`.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_allowed(kp: *mut Keypad, chars: *const c_char) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let chars = unsafe { CStr::from_ptr(chars) };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    drv.set_allowed(KeyMask::from_symbols(chars.to_bytes()));
+    KpError::Ok
+}
+
+/// Switch to `lock` after `timeout_ms` without key presses. Zero timeout disables auto-lock.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_auto_lock(kp: *mut Keypad, timeout_ms: uint32_t, lock: Lock) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let auto_lock = (timeout_ms != 0).then(|| AutoLock {
+        timeout: Duration::from_millis(timeout_ms.into()),
+        lock,
+    });
+    drv.set_auto_lock(auto_lock);
+    KpError::Ok
+}
+
+/// Unlock when the characters of `sequence` are typed while locked. Empty string disables.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_unlock_sequence(kp: *mut Keypad, sequence: *const c_char) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let sequence = unsafe { CStr::from_ptr(sequence) };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let sequence: Vec<Symbol> = sequence.to_bytes().iter().map(|&chr| Symbol::new(chr)).collect();
+    drv.set_unlock_sequence(&sequence);
+    KpError::Ok
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_get_lock(kp: *mut Keypad, lock: *mut Lock) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    unsafe { *lock = drv.get_lock() };
+    KpError::Ok
+}
+
+/// Store `chars` in `buf` of `len` bytes as a NUL-terminated string, at most
+/// `len - 1` characters are stored.
+unsafe fn store_chars(chars: &[u8], buf: *mut c_char, len: usize) -> KpError {
+    if buf.is_null() || len == 0 {
+        return fail(KpError::InvalidArgument);
+    }
+    let count = chars.len().min(len - 1);
+    let buf = unsafe { std::slice::from_raw_parts_mut(buf, count + 1) };
+    for (dst, &chr) in buf.iter_mut().zip(&chars[..count]) {
+        *dst = chr as c_char;
+    }
+    buf[count] = 0;
+    KpError::Ok
+}
+
+/// Store the characters of keys held down, in the order they were pressed, as a
+/// NUL-terminated string. At most `len - 1` characters are stored.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_get_pressed(kp: *mut Keypad, buf: *mut c_char, len: usize) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let pressed: Vec<u8> = drv.pressed_keys().iter().map(|chr| chr.chr()).collect();
+    unsafe { store_chars(&pressed, buf, len) }
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_debounce(kp: *mut Keypad, press: uint32_t, release: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    drv.set_debounce(Debounce { press, release });
+    KpError::Ok
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_load_keymap(kp: *mut Keypad, path: *const c_char) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let path = unsafe { CStr::from_ptr(path) };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let keymap = path
+        .to_str()
+        .map_err(Into::into)
+        .and_then(Keymap::load);
+    match keymap {
+        Ok(keymap) => {
+            drv.set_keymap(keymap);
+            KpError::Ok
+        }
+        Err(e) => fail_parse(e),
+    }
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_load_wiring(kp: *mut Keypad, path: *const c_char) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let path = unsafe { CStr::from_ptr(path) };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let wiring = path
+        .to_str()
+        .map_err(Into::into)
+        .and_then(Wiring::load);
+    match wiring {
+        Ok(wiring) => {
+            drv.set_wiring(wiring);
+            KpError::Ok
+        }
+        Err(e) => fail_parse(e),
+    }
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_repeat(kp: *mut Keypad, delay_ms: uint32_t, interval_ms: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let repeat = (delay_ms != 0).then(|| Repeat {
+        delay: Duration::from_millis(delay_ms.into()),
+        interval: Duration::from_millis(interval_ms.into()),
+    });
+    drv.set_repeat(repeat);
+    KpError::Ok
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_long_press(kp: *mut Keypad, chr: c_char, ms: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let threshold = (ms != 0).then(|| Duration::from_millis(ms.into()));
+    drv.set_long_press(Symbol::new(chr as u8), threshold);
+    KpError::Ok
+}
+
+/// Forward key events to a virtual keyboard named `name`. `keycodes` is the path
+/// of the key code file, NULL uses the codes for the default layout.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_uinput(kp: *mut Keypad, name: *const c_char, keycodes: *const c_char) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let name = unsafe { CStr::from_ptr(name) };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let keycodes = if keycodes.is_null() {
+        Ok(Keycodes::default())
     } else {
-        eprintln!("Keypad not initialized");
+        let path = unsafe { CStr::from_ptr(keycodes) };
+        path.to_str().map_err(Into::into).and_then(Keycodes::load)
+    };
+    let uinput = keycodes.and_then(|keycodes| Uinput::open(&name.to_string_lossy(), keycodes));
+    match uinput {
+        Ok(uinput) => {
+            drv.set_output(Box::new(uinput));
+            KpError::Ok
+        }
+        Err(e) => fail(e),
     }
 }
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn keypad_set_lock(kp: *mut Keypad, lock: Lock) {
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_recovery(kp: *mut Keypad, retries: uint32_t, delay_ms: uint32_t) -> KpError {
     let kp = unsafe { &mut *kp };
-    if let Some(ref mut drv) = kp.driver {
-        drv.set_lock(lock)
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let recovery = (retries != 0).then(|| Recovery {
+        retries,
+        delay: Duration::from_millis(delay_ms.into()),
+    });
+    drv.set_recovery(recovery);
+    KpError::Ok
+}
+
+/// Register key combination `name` of the characters of `keys`, firing after
+/// held for `hold_ms`. `suppress` hides the key events of the combination.
+/// Replaces a combination with the same name.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_add_chord(kp: *mut Keypad, name: *const c_char, keys: *const c_char, hold_ms: uint32_t, suppress: bool) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let name = unsafe { CStr::from_ptr(name) };
+    let keys = unsafe { CStr::from_ptr(keys) };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let Ok(name) = name.to_str() else {
+        return fail(KpError::InvalidArgument);
+    };
+    let chord = Chord {
+        name: name.to_owned(),
+        keys: keys.to_bytes().iter().map(|&chr| Symbol::new(chr)).collect(),
+        hold: Duration::from_millis(hold_ms.into()),
+        suppress,
+    };
+    match drv.add_chord(chord) {
+        Ok(()) => KpError::Ok,
+        Err(e) => fail(e),
+    }
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_remove_chord(kp: *mut Keypad, name: *const c_char) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let name = unsafe { CStr::from_ptr(name) };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    match name.to_str() {
+        Ok(name) if drv.remove_chord(name) => KpError::Ok,
+        _ => fail(KpError::NotFound),
     }
 }
 
+/// Enable multi-tap text entry. `path` is the key assignment file, NULL uses
+/// the phone layout of the right keypad. Discards the current text.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn keypad_get_lock(kp: *mut Keypad) -> Lock {
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_enable_text_entry(kp: *mut Keypad, path: *const c_char) -> KpError {
     let kp = unsafe { &mut *kp };
-    if let Some(ref mut drv) = kp.driver {
-        drv.get_lock()
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let multitap = if path.is_null() {
+        Ok(MultiTap::default())
     } else {
-        Lock::Locked
+        let path = unsafe { CStr::from_ptr(path) };
+        path.to_str().map_err(Into::into).and_then(MultiTap::load)
+    };
+    match multitap {
+        Ok(multitap) => {
+            drv.set_text_entry(Some(multitap));
+            KpError::Ok
+        }
+        Err(e) => fail_parse(e),
     }
 }
 
-pub type KpCallback = unsafe extern "C" fn(c_char, uint32_t);
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_disable_text_entry(kp: *mut Keypad) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    drv.set_text_entry(None);
+    KpError::Ok
+}
+
+/// Store the committed text as a NUL-terminated string. At most `len - 1`
+/// characters are stored.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_get_text(kp: *mut Keypad, buf: *mut c_char, len: usize) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    unsafe { store_chars(drv.get_text().as_bytes(), buf, len) }
+}
 
+/// Discard the text and the character under composition.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn keypad_set_on_pressed(kp: *mut Keypad, callback: KpCallback, arg: uint32_t) {
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_clear_text(kp: *mut Keypad) -> KpError {
     let kp = unsafe { &mut *kp };
-    if let Some(ref mut drv) = kp.driver {
-        let cb = move |sym: Symbol| {
-            let chr = sym.chr() as c_char;
-            unsafe {
-                callback(chr, arg);
-            }
-        };
-        drv.set_on_pressed(Box::new(cb));
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    drv.clear_text();
+    KpError::Ok
+}
+
+/// Key event as seen by C code.
+#[repr(C)]
+pub struct KpEvent {
+    symbol: c_char,
+    kind: KeyEventKind,
+    /// Milliseconds since keypad initialization.
+    timestamp_ms: uint64_t,
+    /// Matrix position of the key.
+    pad: uint8_t,
+    row: uint8_t,
+    column: uint8_t,
+}
+
+impl KpEvent {
+    fn new(ev: &KeyEvent, epoch: Instant) -> Self {
+        let timestamp = ev.timestamp.saturating_duration_since(epoch);
+        Self {
+            symbol: ev.symbol.chr() as c_char,
+            kind: ev.kind,
+            timestamp_ms: timestamp.as_millis() as uint64_t,
+            pad: ev.pad as uint8_t,
+            row: ev.row as uint8_t,
+            column: ev.column as uint8_t,
+        }
     }
 }
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn keypad_set_on_released(kp: *mut Keypad, callback: KpCallback, arg: uint32_t) {
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_poll_event(kp: *mut Keypad, event: *mut KpEvent) -> KpError {
     let kp = unsafe { &mut *kp };
-    if let Some(ref mut drv) = kp.driver {
-        let cb = move |sym: Symbol| {
-            let chr = sym.chr() as c_char;
-            unsafe {
-                callback(chr, arg);
-            }
-        };
-        drv.set_on_released(Box::new(cb));
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    match drv.try_recv() {
+        Some(ev) => {
+            unsafe { *event = KpEvent::new(&ev, drv.epoch()) };
+            KpError::Ok
+        }
+        None => KpError::NoEvent,
     }
 }
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_wait_event(kp: *mut Keypad, event: *mut KpEvent, timeout_ms: c_int) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let ev = if timeout_ms < 0 {
+        Some(drv.recv())
+    } else {
+        drv.recv_timeout(Duration::from_millis(timeout_ms as u64))
+    };
+    match ev {
+        Some(ev) => {
+            unsafe { *event = KpEvent::new(&ev, drv.epoch()) };
+            KpError::Ok
+        }
+        None => KpError::Timeout,
+    }
+}
+
+pub type KpCallback = unsafe extern "C" fn(c_char, uint32_t);
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_pressed(kp: *mut Keypad, callback: KpCallback, arg: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let cb = move |sym: Symbol| {
+        let chr = sym.chr() as c_char;
+        unsafe {
+            callback(chr, arg);
+        }
+    };
+    drv.set_on_pressed(Box::new(cb));
+    KpError::Ok
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_released(kp: *mut Keypad, callback: KpCallback, arg: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let cb = move |sym: Symbol| {
+        let chr = sym.chr() as c_char;
+        unsafe {
+            callback(chr, arg);
+        }
+    };
+    drv.set_on_released(Box::new(cb));
+    KpError::Ok
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_long_press(kp: *mut Keypad, callback: KpCallback, arg: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let cb = move |sym: Symbol| {
+        let chr = sym.chr() as c_char;
+        unsafe {
+            callback(chr, arg);
+        }
+    };
+    drv.set_on_long_press(Box::new(cb));
+    KpError::Ok
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_repeat(kp: *mut Keypad, callback: KpCallback, arg: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let cb = move |sym: Symbol| {
+        let chr = sym.chr() as c_char;
+        unsafe {
+            callback(chr, arg);
+        }
+    };
+    drv.set_on_repeat(Box::new(cb));
+    KpError::Ok
+}
+
+pub type KpEventCallback = unsafe extern "C" fn(*const KpEvent, uint32_t);
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_event(kp: *mut Keypad, callback: KpEventCallback, arg: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let epoch = drv.epoch();
+    let cb = move |ev: &KeyEvent| {
+        let event = KpEvent::new(ev, epoch);
+        unsafe {
+            callback(&event, arg);
+        }
+    };
+    drv.set_on_event(Box::new(cb));
+    KpError::Ok
+}
+
+pub type KpRecoveryCallback = unsafe extern "C" fn(RecoveryEvent, uint32_t);
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_recovery(kp: *mut Keypad, callback: KpRecoveryCallback, arg: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let cb = move |event: RecoveryEvent| unsafe {
+        callback(event, arg);
+    };
+    drv.set_on_recovery(Box::new(cb));
+    KpError::Ok
+}
+
+pub type KpLockCallback = unsafe extern "C" fn(Lock, uint32_t);
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_lock_changed(kp: *mut Keypad, callback: KpLockCallback, arg: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let cb = move |lock: Lock| unsafe {
+        callback(lock, arg);
+    };
+    drv.set_on_lock_changed(Box::new(cb));
+    KpError::Ok
+}
+
+pub type KpRejectedCallback = unsafe extern "C" fn(c_char, Lock, uint32_t);
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_rejected(kp: *mut Keypad, callback: KpRejectedCallback, arg: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let cb = move |sym: Symbol, lock: Lock| {
+        let chr = sym.chr() as c_char;
+        unsafe {
+            callback(chr, lock, arg);
+        }
+    };
+    drv.set_on_rejected(Box::new(cb));
+    KpError::Ok
+}
+
+pub type KpChordCallback = unsafe extern "C" fn(*const c_char, uint32_t);
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_chord(kp: *mut Keypad, callback: KpChordCallback, arg: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let cb = move |name: &str| {
+        // Names come from C strings, so they contain no NUL.
+        let name = CString::new(name).unwrap_or_default();
+        unsafe {
+            callback(name.as_ptr(), arg);
+        }
+    };
+    drv.set_on_chord(Box::new(cb));
+    KpError::Ok
+}
+
+pub type KpTextCallback = unsafe extern "C" fn(TextEventKind, c_char, uint32_t);
+
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_text(kp: *mut Keypad, callback: KpTextCallback, arg: uint32_t) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let cb = move |event: TextEvent| unsafe {
+        callback(event.kind, event.chr as c_char, arg);
+    };
+    drv.set_on_text(Box::new(cb));
+    KpError::Ok
+}
+
+/// Releases `user_data` of a callback.
+pub type KpDestroy = unsafe extern "C" fn(*mut c_void);
+
+/// User data of a callback, released when the callback is replaced or the keypad is deleted.
+struct UserData {
+    ptr: *mut c_void,
+    destroy: Option<KpDestroy>,
+}
+
+// The C side is responsible for making user data usable from the scanning thread.
+unsafe impl Send for UserData {}
+
+impl UserData {
+    fn new(ptr: *mut c_void, destroy: Option<KpDestroy>) -> Self {
+        Self { ptr, destroy }
+    }
+
+    fn ptr(&self) -> *mut c_void {
+        self.ptr
+    }
+}
+
+impl Drop for UserData {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.destroy {
+            unsafe { destroy(self.ptr) }
+        }
+    }
+}
+
+pub type KpCallbackEx = unsafe extern "C" fn(c_char, *mut c_void);
+
+fn key_callback_ex(callback: KpCallbackEx, data: UserData) -> Callback {
+    Box::new(move |sym: Symbol| {
+        let chr = sym.chr() as c_char;
+        unsafe {
+            callback(chr, data.ptr());
+        }
+    })
+}
+
+/// Same as `keypad_set_on_pressed` with a pointer argument. `destroy` (may be NULL)
+/// is called with `user_data` when the callback is replaced or the keypad is deleted,
+/// but not if this function fails.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_pressed_ex(kp: *mut Keypad, callback: KpCallbackEx, user_data: *mut c_void, destroy: Option<KpDestroy>) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    drv.set_on_pressed(key_callback_ex(callback, UserData::new(user_data, destroy)));
+    KpError::Ok
+}
+
+/// Same as `keypad_set_on_released` with a pointer argument, see `keypad_set_on_pressed_ex`.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_released_ex(kp: *mut Keypad, callback: KpCallbackEx, user_data: *mut c_void, destroy: Option<KpDestroy>) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    drv.set_on_released(key_callback_ex(callback, UserData::new(user_data, destroy)));
+    KpError::Ok
+}
+
+/// Same as `keypad_set_on_long_press` with a pointer argument, see `keypad_set_on_pressed_ex`.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_long_press_ex(kp: *mut Keypad, callback: KpCallbackEx, user_data: *mut c_void, destroy: Option<KpDestroy>) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    drv.set_on_long_press(key_callback_ex(callback, UserData::new(user_data, destroy)));
+    KpError::Ok
+}
+
+/// Same as `keypad_set_on_repeat` with a pointer argument, see `keypad_set_on_pressed_ex`.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_repeat_ex(kp: *mut Keypad, callback: KpCallbackEx, user_data: *mut c_void, destroy: Option<KpDestroy>) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    drv.set_on_repeat(key_callback_ex(callback, UserData::new(user_data, destroy)));
+    KpError::Ok
+}
+
+pub type KpEventCallbackEx = unsafe extern "C" fn(*const KpEvent, *mut c_void);
+
+/// Same as `keypad_set_on_event` with a pointer argument, see `keypad_set_on_pressed_ex`.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_event_ex(kp: *mut Keypad, callback: KpEventCallbackEx, user_data: *mut c_void, destroy: Option<KpDestroy>) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let epoch = drv.epoch();
+    let data = UserData::new(user_data, destroy);
+    let cb = move |ev: &KeyEvent| {
+        let event = KpEvent::new(ev, epoch);
+        unsafe {
+            callback(&event, data.ptr());
+        }
+    };
+    drv.set_on_event(Box::new(cb));
+    KpError::Ok
+}
+
+pub type KpRecoveryCallbackEx = unsafe extern "C" fn(RecoveryEvent, *mut c_void);
+
+/// Same as `keypad_set_on_recovery` with a pointer argument, see `keypad_set_on_pressed_ex`.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_recovery_ex(kp: *mut Keypad, callback: KpRecoveryCallbackEx, user_data: *mut c_void, destroy: Option<KpDestroy>) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let data = UserData::new(user_data, destroy);
+    let cb = move |event: RecoveryEvent| unsafe {
+        callback(event, data.ptr());
+    };
+    drv.set_on_recovery(Box::new(cb));
+    KpError::Ok
+}
+
+pub type KpLockCallbackEx = unsafe extern "C" fn(Lock, *mut c_void);
+
+/// Same as `keypad_set_on_lock_changed` with a pointer argument, see `keypad_set_on_pressed_ex`.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_lock_changed_ex(kp: *mut Keypad, callback: KpLockCallbackEx, user_data: *mut c_void, destroy: Option<KpDestroy>) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let data = UserData::new(user_data, destroy);
+    let cb = move |lock: Lock| unsafe {
+        callback(lock, data.ptr());
+    };
+    drv.set_on_lock_changed(Box::new(cb));
+    KpError::Ok
+}
+
+pub type KpRejectedCallbackEx = unsafe extern "C" fn(c_char, Lock, *mut c_void);
+
+/// Same as `keypad_set_on_rejected` with a pointer argument, see `keypad_set_on_pressed_ex`.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_rejected_ex(kp: *mut Keypad, callback: KpRejectedCallbackEx, user_data: *mut c_void, destroy: Option<KpDestroy>) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let data = UserData::new(user_data, destroy);
+    let cb = move |sym: Symbol, lock: Lock| {
+        let chr = sym.chr() as c_char;
+        unsafe {
+            callback(chr, lock, data.ptr());
+        }
+    };
+    drv.set_on_rejected(Box::new(cb));
+    KpError::Ok
+}
+
+pub type KpChordCallbackEx = unsafe extern "C" fn(*const c_char, *mut c_void);
+
+/// Same as `keypad_set_on_chord` with a pointer argument, see `keypad_set_on_pressed_ex`.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_chord_ex(kp: *mut Keypad, callback: KpChordCallbackEx, user_data: *mut c_void, destroy: Option<KpDestroy>) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let data = UserData::new(user_data, destroy);
+    let cb = move |name: &str| {
+        let name = CString::new(name).unwrap_or_default();
+        unsafe {
+            callback(name.as_ptr(), data.ptr());
+        }
+    };
+    drv.set_on_chord(Box::new(cb));
+    KpError::Ok
+}
+
+pub type KpTextCallbackEx = unsafe extern "C" fn(TextEventKind, c_char, *mut c_void);
+
+/// Same as `keypad_set_on_text` with a pointer argument, see `keypad_set_on_pressed_ex`.
+#[unsafe(no_mangle)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn keypad_set_on_text_ex(kp: *mut Keypad, callback: KpTextCallbackEx, user_data: *mut c_void, destroy: Option<KpDestroy>) -> KpError {
+    let kp = unsafe { &mut *kp };
+    let Some(ref drv) = kp.driver else {
+        return fail(KpError::NotInitialized);
+    };
+    let data = UserData::new(user_data, destroy);
+    let cb = move |event: TextEvent| unsafe {
+        callback(event.kind, event.chr as c_char, data.ptr());
+    };
+    drv.set_on_text(Box::new(cb));
+    KpError::Ok
+}
@@ -0,0 +1,71 @@
+//! Helpers for the line based text representations of configuration files.
+
+use anyhow::{Error, bail};
+use std::str::SplitWhitespace;
+
+use super::layout::Symbol;
+
+/// Lines of `text` with their numbers counted from 1, without empty lines
+/// and lines starting with `#`.
+pub(crate) fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(n, line)| (n + 1, line))
+        .filter(|(_, line)| {
+            let line = line.trim_start();
+            !line.is_empty() && !line.starts_with('#')
+        })
+}
+
+/// Whitespace separated fields of line `n`.
+pub(crate) struct Fields<'a> {
+    n: usize,
+    fields: SplitWhitespace<'a>,
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(n: usize, line: &'a str) -> Self {
+        Self {
+            n,
+            fields: line.split_whitespace(),
+        }
+    }
+
+    /// Parse the next field as a number below `limit`, `name` is used in errors.
+    pub fn index(&mut self, name: &str, limit: usize) -> Result<usize, Error> {
+        let n = self.n;
+        let Some(field) = self.next() else {
+            bail!("line {n}: missing {name}");
+        };
+        match field.parse() {
+            Ok(idx) if idx < limit => Ok(idx),
+            _ => bail!("line {n}: invalid {name} `{field}`"),
+        }
+    }
+
+    /// Parse the next field as a symbol.
+    pub fn symbol(&mut self) -> Result<Symbol, Error> {
+        let n = self.n;
+        match self.next().map(str::as_bytes) {
+            Some(&[chr]) if chr.is_ascii_graphic() => Ok(Symbol::new(chr)),
+            Some(_) => bail!("line {n}: symbol must be a single printable ASCII character"),
+            None => bail!("line {n}: missing symbol"),
+        }
+    }
+
+    /// Fail if there are fields left.
+    pub fn end(mut self) -> Result<(), Error> {
+        match self.next() {
+            Some(field) => bail!("line {}: unexpected `{field}`", self.n),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.fields.next()
+    }
+}
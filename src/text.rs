@@ -0,0 +1,391 @@
+//! Phone-style multi-tap text entry.
+//!
+//! Pressing a key repeatedly cycles through its characters (`2` → `a`, `b`,
+//! `c`, `2`). The character is committed when another key is pressed or the
+//! key is not pressed again within the timeout.
+
+use anyhow::{Error, bail};
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use super::{layout::Symbol, parse};
+
+/// Kind of text event.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEventKind {
+    /// Character under composition changed, it may still change.
+    Composing = 0,
+    /// Character was appended to the text.
+    Committed = 1,
+    /// Character under composition was discarded.
+    Cancelled = 2,
+    /// Last character of the text was deleted.
+    Deleted = 3,
+}
+
+/// Text entry event, with the character concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextEvent {
+    pub kind: TextEventKind,
+    pub chr: u8,
+}
+
+/// Multi-tap key assignment.
+///
+/// Text representation has one key per line: the key symbol, a single space
+/// and the characters it cycles through, up to the end of the line (so a
+/// space may be one of them). Lines `backspace <symbol>`, `shift <symbol>`
+/// and `timeout <ms>` set the special keys and the commit timeout. Empty
+/// lines and lines starting with `#` are ignored, so `#` can only be a
+/// special key.
+///
+/// ```text
+/// 2 abc2
+/// 0  0
+/// backspace *
+/// shift #
+/// timeout 1000
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiTap {
+    keys: HashMap<Symbol, Vec<u8>>,
+    /// Deletes the last character.
+    backspace: Option<Symbol>,
+    /// Toggles upper case.
+    shift: Option<Symbol>,
+    timeout: Duration,
+}
+
+#[rustfmt::skip]
+const KEYS: [(u8, &[u8]); 10] = [
+    (b'1', b".,?!1"), (b'2', b"abc2"), (b'3', b"def3"),
+    (b'4', b"ghi4"), (b'5', b"jkl5"), (b'6', b"mno6"),
+    (b'7', b"pqrs7"), (b'8', b"tuv8"), (b'9', b"wxyz9"),
+    (b'0', b" 0"),
+];
+
+impl Default for MultiTap {
+    /// Phone layout of the right keypad, `*` deletes and `#` switches case.
+    fn default() -> Self {
+        let keys = KEYS
+            .iter()
+            .map(|&(chr, chars)| (Symbol::new(chr), chars.to_vec()))
+            .collect();
+        Self {
+            keys,
+            backspace: Some(Symbol::new(b'*')),
+            shift: Some(Symbol::new(b'#')),
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+impl MultiTap {
+    /// Assignment without any keys.
+    pub fn empty() -> Self {
+        Self {
+            keys: HashMap::new(),
+            backspace: None,
+            shift: None,
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// Load assignment from a file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text)
+    }
+
+    /// Parse assignment from its text representation.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut multitap = Self::empty();
+        for (n, line) in parse::lines(text) {
+            let (name, value) = line.split_once(' ').unwrap_or((line, ""));
+            let symbol = || match value.trim().as_bytes() {
+                &[chr] if chr.is_ascii_graphic() => Ok(Symbol::new(chr)),
+                _ => bail!("line {n}: symbol must be a single printable ASCII character"),
+            };
+            match name {
+                "backspace" => multitap.backspace = Some(symbol()?),
+                "shift" => multitap.shift = Some(symbol()?),
+                "timeout" => match value.trim().parse() {
+                    Ok(ms) => multitap.timeout = Duration::from_millis(ms),
+                    Err(_) => bail!("line {n}: invalid timeout `{value}`"),
+                },
+                _ => {
+                    let &[chr] = name.as_bytes() else {
+                        bail!("line {n}: expected a key symbol, found `{name}`");
+                    };
+                    if value.is_empty() || !value.bytes().all(|c| c == b' ' || c.is_ascii_graphic())
+                    {
+                        bail!("line {n}: characters must be printable ASCII");
+                    }
+                    if multitap
+                        .keys
+                        .insert(Symbol::new(chr), value.into())
+                        .is_some()
+                    {
+                        bail!("line {n}: key `{}` assigned twice", chr as char);
+                    }
+                }
+            }
+        }
+        Ok(multitap)
+    }
+
+    /// Assign characters to a key, an empty slice removes the key.
+    pub fn insert(&mut self, chr: Symbol, chars: &[u8]) {
+        if chars.is_empty() {
+            self.keys.remove(&chr);
+        } else {
+            self.keys.insert(chr, chars.to_vec());
+        }
+    }
+
+    /// Set the key deleting the last character.
+    pub fn set_backspace(&mut self, chr: Option<Symbol>) {
+        self.backspace = chr;
+    }
+
+    /// Set the key toggling upper case.
+    pub fn set_shift(&mut self, chr: Option<Symbol>) {
+        self.shift = chr;
+    }
+
+    /// Set time after which the character under composition is committed.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+}
+
+/// Character under composition.
+#[derive(Debug, Clone, Copy)]
+struct Composing {
+    key: Symbol,
+    index: usize,
+    deadline: Instant,
+}
+
+/// Multi-tap text entry state.
+#[derive(Debug, Clone)]
+pub struct TextEntry {
+    multitap: MultiTap,
+    composing: Option<Composing>,
+    upper: bool,
+    text: String,
+}
+
+impl TextEntry {
+    pub fn new(multitap: MultiTap) -> Self {
+        Self {
+            multitap,
+            composing: None,
+            upper: false,
+            text: String::new(),
+        }
+    }
+
+    /// Process a key press at `now`. Returns the resulting events.
+    pub fn key(&mut self, chr: Symbol, now: Instant) -> Vec<TextEvent> {
+        let mut events: Vec<TextEvent> = self.poll(now).into_iter().collect();
+
+        if Some(chr) == self.multitap.backspace {
+            if let Some(composing) = self.composing.take() {
+                events.push(self.event(TextEventKind::Cancelled, composing));
+            } else if let Some(last) = self.text.pop() {
+                events.push(TextEvent {
+                    kind: TextEventKind::Deleted,
+                    chr: last as u8,
+                });
+            }
+        } else if Some(chr) == self.multitap.shift {
+            self.upper = !self.upper;
+            if let Some(composing) = self.composing {
+                events.push(self.event(TextEventKind::Composing, composing));
+            }
+        } else if let Some(count) = self.multitap.keys.get(&chr).map(Vec::len) {
+            let index = match self.composing {
+                Some(composing) if composing.key == chr => (composing.index + 1) % count,
+                _ => {
+                    events.extend(self.commit());
+                    0
+                }
+            };
+            let composing = Composing {
+                key: chr,
+                index,
+                deadline: now + self.multitap.timeout,
+            };
+            self.composing = Some(composing);
+            events.push(self.event(TextEventKind::Composing, composing));
+        } else {
+            events.extend(self.commit());
+        }
+        events
+    }
+
+    /// Commit the character under composition if its timeout expired at `now`.
+    pub fn poll(&mut self, now: Instant) -> Option<TextEvent> {
+        match self.composing {
+            Some(composing) if now >= composing.deadline => self.commit(),
+            _ => None,
+        }
+    }
+
+    /// Commit the character under composition now.
+    pub fn commit(&mut self) -> Option<TextEvent> {
+        let composing = self.composing.take()?;
+        let event = self.event(TextEventKind::Committed, composing);
+        self.text.push(event.chr as char);
+        Some(event)
+    }
+
+    /// Get the committed text.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Discard the text and the character under composition.
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.composing = None;
+    }
+
+    fn event(&self, kind: TextEventKind, composing: Composing) -> TextEvent {
+        let chr = self.multitap.keys[&composing.key][composing.index];
+        let chr = if self.upper {
+            chr.to_ascii_uppercase()
+        } else {
+            chr
+        };
+        TextEvent { kind, chr }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TextEventKind::*;
+
+    /// Text entry fed with keys at times in milliseconds.
+    struct Typing {
+        entry: TextEntry,
+        start: Instant,
+    }
+
+    impl Typing {
+        fn new(multitap: MultiTap) -> Self {
+            Self {
+                entry: TextEntry::new(multitap),
+                start: Instant::now(),
+            }
+        }
+
+        fn key(&mut self, chr: u8, ms: u64) -> Vec<(TextEventKind, char)> {
+            let now = self.start + Duration::from_millis(ms);
+            let events = self.entry.key(Symbol::new(chr), now);
+            events.iter().map(|ev| (ev.kind, ev.chr as char)).collect()
+        }
+
+        fn poll(&mut self, ms: u64) -> Option<(TextEventKind, char)> {
+            let now = self.start + Duration::from_millis(ms);
+            self.entry.poll(now).map(|ev| (ev.kind, ev.chr as char))
+        }
+    }
+
+    #[test]
+    fn keys_cycle_through_characters() {
+        let mut typing = Typing::new(MultiTap::default());
+        assert_eq!(typing.key(b'2', 0), [(Composing, 'a')]);
+        assert_eq!(typing.key(b'2', 100), [(Composing, 'b')]);
+        assert_eq!(typing.key(b'2', 200), [(Composing, 'c')]);
+        assert_eq!(typing.key(b'2', 300), [(Composing, '2')]);
+        assert_eq!(typing.key(b'2', 400), [(Composing, 'a')]);
+        assert_eq!(typing.key(b'3', 500), [(Committed, 'a'), (Composing, 'd')]);
+        assert_eq!(typing.poll(1499), None);
+        assert_eq!(typing.poll(1500), Some((Committed, 'd')));
+        assert_eq!(typing.poll(3000), None);
+
+        // Timeout commits before the key is processed.
+        assert_eq!(typing.key(b'0', 3000), [(Composing, ' ')]);
+        assert_eq!(typing.key(b'0', 4500), [(Committed, ' '), (Composing, ' ')]);
+        // Keys without characters commit.
+        assert_eq!(typing.key(b'A', 4600), [(Committed, ' ')]);
+        assert_eq!(typing.key(b'A', 4700), []);
+        assert_eq!(typing.entry.text(), "ad  ");
+    }
+
+    #[test]
+    fn backspace_and_shift() {
+        let mut typing = Typing::new(MultiTap::default());
+        assert_eq!(typing.key(b'4', 0), [(Composing, 'g')]);
+        assert_eq!(typing.key(b'#', 100), [(Composing, 'G')]);
+        assert_eq!(typing.key(b'4', 200), [(Composing, 'H')]);
+        assert_eq!(typing.key(b'5', 300), [(Committed, 'H'), (Composing, 'J')]);
+        assert_eq!(typing.key(b'*', 400), [(Cancelled, 'J')]);
+        assert_eq!(typing.key(b'#', 500), []);
+        assert_eq!(typing.key(b'6', 600), [(Composing, 'm')]);
+        assert_eq!(
+            typing.entry.commit(),
+            Some(TextEvent {
+                kind: Committed,
+                chr: b'm'
+            })
+        );
+        assert_eq!(typing.entry.text(), "Hm");
+
+        assert_eq!(typing.key(b'*', 700), [(Deleted, 'm')]);
+        assert_eq!(typing.key(b'*', 800), [(Deleted, 'H')]);
+        assert_eq!(typing.key(b'*', 900), []);
+        assert_eq!(typing.entry.text(), "");
+
+        typing.key(b'7', 1000);
+        typing.entry.clear();
+        assert_eq!(typing.entry.commit(), None);
+    }
+
+    #[test]
+    fn parse_multitap() {
+        let text = "# names only\n2 ab\n0  0\n\nbackspace A\nshift B\ntimeout 50\n";
+        let multitap = MultiTap::parse(text).unwrap();
+        let mut expected = MultiTap::empty();
+        expected.insert(Symbol::new(b'2'), b"ab");
+        expected.insert(Symbol::new(b'0'), b" 0");
+        expected.set_backspace(Some(Symbol::new(b'A')));
+        expected.set_shift(Some(Symbol::new(b'B')));
+        expected.set_timeout(Duration::from_millis(50));
+        assert_eq!(multitap, expected);
+
+        let mut typing = Typing::new(multitap);
+        assert_eq!(typing.key(b'0', 0), [(Composing, ' ')]);
+        assert_eq!(typing.key(b'3', 10), [(Committed, ' ')]);
+        assert_eq!(typing.key(b'2', 20), [(Composing, 'a')]);
+        assert_eq!(typing.key(b'B', 30), [(Composing, 'A')]);
+        assert_eq!(typing.poll(80), Some((Committed, 'A')));
+        assert_eq!(typing.entry.text(), " A");
+    }
+
+    #[test]
+    fn parse_multitap_errors() {
+        let error = |text: &str| MultiTap::parse(text).unwrap_err().to_string();
+
+        assert_eq!(error("22 ab"), "line 1: expected a key symbol, found `22`");
+        assert_eq!(error("2"), "line 1: characters must be printable ASCII");
+        assert_eq!(
+            error("2 a\tb"),
+            "line 1: characters must be printable ASCII"
+        );
+        assert_eq!(error("2 ab\n2 cd"), "line 2: key `2` assigned twice");
+        assert_eq!(
+            error("backspace **"),
+            "line 1: symbol must be a single printable ASCII character"
+        );
+        assert_eq!(error("timeout 1s"), "line 1: invalid timeout `1s`");
+    }
+}
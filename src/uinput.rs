@@ -0,0 +1,368 @@
+//! Keypad as a Linux input device.
+//!
+//! `Uinput` translates key events into `EV_KEY` input events and writes them
+//! to an `InputWriter`, normally a virtual keyboard created through
+//! `/dev/uinput`.
+
+use anyhow::{Error, bail};
+use std::{
+    collections::HashMap,
+    ffi::c_char,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    mem,
+    os::fd::AsRawFd,
+    path::Path,
+    slice,
+};
+
+use super::{
+    event::{KeyEvent, KeyEventKind, Output},
+    layout::Symbol,
+    parse::{self, Fields},
+};
+
+const UINPUT: &str = "/dev/uinput";
+
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const SYN_REPORT: u16 = 0;
+
+/// Value of a key event.
+const RELEASED: i32 = 0;
+const PRESSED: i32 = 1;
+const REPEATED: i32 = 2;
+
+const UI_DEV_CREATE: libc::Ioctl = libc::_IO(b'U' as u32, 1);
+const UI_DEV_DESTROY: libc::Ioctl = libc::_IO(b'U' as u32, 2);
+const UI_DEV_SETUP: libc::Ioctl = libc::_IOW::<libc::uinput_setup>(b'U' as u32, 3);
+const UI_SET_EVBIT: libc::Ioctl = libc::_IOW::<libc::c_int>(b'U' as u32, 100);
+const UI_SET_KEYBIT: libc::Ioctl = libc::_IOW::<libc::c_int>(b'U' as u32, 101);
+
+const BUS_VIRTUAL: u16 = 0x06;
+
+/// Input event without the timestamp (filled in by the kernel).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    /// Event type, e.g. `EV_KEY`.
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// Destination of input events.
+pub trait InputWriter: Send {
+    /// Write a batch of events, terminated with `SYN_REPORT`.
+    fn write(&mut self, events: &[InputEvent]) -> Result<(), Error>;
+}
+
+/// Virtual keyboard created through `/dev/uinput`.
+pub struct UinputDevice {
+    file: File,
+}
+
+impl UinputDevice {
+    /// Create a keyboard named `name` able to emit the keys of `keycodes`.
+    pub fn new(name: &str, keycodes: &Keycodes) -> Result<Self, Error> {
+        Self::with_path(UINPUT, name, keycodes)
+    }
+
+    /// Same as `new` with a non-standard uinput device path.
+    pub fn with_path(
+        path: impl AsRef<Path>,
+        name: &str,
+        keycodes: &Keycodes,
+    ) -> Result<Self, Error> {
+        let file = OpenOptions::new().write(true).open(path)?;
+        let fd = file.as_raw_fd();
+
+        ioctl(fd, UI_SET_EVBIT, EV_KEY.into())?;
+        for code in keycodes.codes.values() {
+            ioctl(fd, UI_SET_KEYBIT, (*code).into())?;
+        }
+
+        let mut setup: libc::uinput_setup = unsafe { mem::zeroed() };
+        setup.id.bustype = BUS_VIRTUAL;
+        // Keep the terminating zero.
+        let len = name.len().min(setup.name.len() - 1);
+        for (dst, src) in setup.name.iter_mut().zip(&name.as_bytes()[..len]) {
+            *dst = *src as c_char;
+        }
+        if unsafe { libc::ioctl(fd, UI_DEV_SETUP, &setup) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        if unsafe { libc::ioctl(fd, UI_DEV_CREATE) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Self { file })
+    }
+}
+
+impl InputWriter for UinputDevice {
+    fn write(&mut self, events: &[InputEvent]) -> Result<(), Error> {
+        let raw: Vec<libc::input_event> = events
+            .iter()
+            .map(|ev| {
+                // Layout of the timestamp differs between architectures, zero is fine for all.
+                let mut raw: libc::input_event = unsafe { mem::zeroed() };
+                raw.type_ = ev.kind;
+                raw.code = ev.code;
+                raw.value = ev.value;
+                raw
+            })
+            .collect();
+        let bytes = unsafe {
+            slice::from_raw_parts(raw.as_ptr().cast::<u8>(), mem::size_of_val(raw.as_slice()))
+        };
+        self.file.write_all(bytes)?;
+        Ok(())
+    }
+}
+
+impl Drop for UinputDevice {
+    fn drop(&mut self) {
+        unsafe { libc::ioctl(self.file.as_raw_fd(), UI_DEV_DESTROY) };
+    }
+}
+
+fn ioctl(fd: libc::c_int, request: libc::Ioctl, arg: libc::c_int) -> Result<(), io::Error> {
+    if unsafe { libc::ioctl(fd, request, arg) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Assignment of Linux key codes to symbols.
+///
+/// Text representation has one key per line: the key code (decimal or `0x`
+/// hexadecimal, see `linux/input-event-codes.h`) and the symbol character.
+/// Empty lines and lines starting with `#` are ignored.
+///
+/// ```text
+/// # code symbol
+/// 30 A
+/// 0x20b #
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keycodes {
+    codes: HashMap<Symbol, u16>,
+}
+
+#[rustfmt::skip]
+const KEYCODES: [(u8, u16); 24] = [
+    (b'A', 30), (b'B', 48), (b'C', 46), (b'D', 32), (b'E', 18), (b'F', 33),
+    (b'G', 34), (b'H', 35), (b'I', 23), (b'J', 36), (b'K', 37), (b'L', 38),
+    (b'1', 2), (b'2', 3), (b'3', 4), (b'4', 5), (b'5', 6), (b'6', 7),
+    (b'7', 8), (b'8', 9), (b'9', 10), (b'0', 11),
+    (b'*', 0x20a), // KEY_NUMERIC_STAR
+    (b'#', 0x20b), // KEY_NUMERIC_POUND
+];
+
+impl Default for Keycodes {
+    /// Key codes for the default layout.
+    fn default() -> Self {
+        let codes = KEYCODES
+            .iter()
+            .map(|&(chr, code)| (Symbol::new(chr), code))
+            .collect();
+        Self { codes }
+    }
+}
+
+impl Keycodes {
+    /// Assignment without any keys.
+    pub fn empty() -> Self {
+        Self {
+            codes: HashMap::new(),
+        }
+    }
+
+    /// Load key codes from a file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text)
+    }
+
+    /// Parse key codes from their text representation.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut keycodes = Self::empty();
+        for (n, line) in parse::lines(text) {
+            let mut fields = Fields::new(n, line);
+            let field = fields.next().unwrap_or_default();
+            let code = match field.strip_prefix("0x") {
+                Some(hex) => u16::from_str_radix(hex, 16),
+                None => field.parse(),
+            };
+            let code = match code {
+                Ok(code) if code <= libc::KEY_MAX => code,
+                _ => bail!("line {n}: invalid key code `{field}`"),
+            };
+            let symbol = fields.symbol()?;
+            fields.end()?;
+            if keycodes.codes.insert(symbol, code).is_some() {
+                bail!("line {n}: symbol `{}` assigned twice", symbol.chr() as char);
+            }
+        }
+        Ok(keycodes)
+    }
+
+    /// Assign key code to a symbol.
+    pub fn insert(&mut self, chr: Symbol, code: u16) {
+        self.codes.insert(chr, code);
+    }
+
+    /// Get key code of a symbol.
+    pub fn get(&self, chr: Symbol) -> Option<u16> {
+        self.codes.get(&chr).copied()
+    }
+}
+
+/// Output backend writing key events as Linux input events.
+pub struct Uinput<W = UinputDevice> {
+    writer: W,
+    keycodes: Keycodes,
+}
+
+impl Uinput {
+    /// Create a virtual keyboard named `name`.
+    pub fn open(name: &str, keycodes: Keycodes) -> Result<Self, Error> {
+        let writer = UinputDevice::new(name, &keycodes)?;
+        Ok(Self::new(writer, keycodes))
+    }
+}
+
+impl<W: InputWriter> Uinput<W> {
+    /// Write events to the given writer.
+    pub fn new(writer: W, keycodes: Keycodes) -> Self {
+        Self { writer, keycodes }
+    }
+
+    /// Get the writer.
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+}
+
+impl<W: InputWriter> Output for Uinput<W> {
+    fn event(&mut self, event: &KeyEvent) -> Result<(), Error> {
+        let value = match event.kind {
+            KeyEventKind::Pressed => PRESSED,
+            KeyEventKind::Released => RELEASED,
+            KeyEventKind::Repeat => REPEATED,
+            KeyEventKind::LongPress => return Ok(()),
+        };
+        // Keys without a code are not forwarded.
+        let Some(code) = self.keycodes.get(event.symbol) else {
+            return Ok(());
+        };
+        self.writer.write(&[
+            InputEvent {
+                kind: EV_KEY,
+                code,
+                value,
+            },
+            InputEvent {
+                kind: EV_SYN,
+                code: SYN_REPORT,
+                value: 0,
+            },
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Writer keeping the events written to it.
+    #[derive(Default)]
+    struct Recorder(Vec<Vec<InputEvent>>);
+
+    impl InputWriter for Recorder {
+        fn write(&mut self, events: &[InputEvent]) -> Result<(), Error> {
+            self.0.push(events.to_vec());
+            Ok(())
+        }
+    }
+
+    fn event(kind: KeyEventKind, chr: u8) -> KeyEvent {
+        KeyEvent {
+            symbol: Symbol::new(chr),
+            kind,
+            timestamp: Instant::now(),
+            pad: 0,
+            row: 0,
+            column: 0,
+        }
+    }
+
+    fn key(code: u16, value: i32) -> Vec<InputEvent> {
+        let syn = InputEvent {
+            kind: EV_SYN,
+            code: SYN_REPORT,
+            value: 0,
+        };
+        vec![
+            InputEvent {
+                kind: EV_KEY,
+                code,
+                value,
+            },
+            syn,
+        ]
+    }
+
+    #[test]
+    fn key_events_are_written() {
+        let mut keycodes = Keycodes::default();
+        keycodes.insert(Symbol::new(b'+'), 78);
+        let mut uinput = Uinput::new(Recorder::default(), keycodes);
+        for (kind, chr) in [
+            (KeyEventKind::Pressed, b'A'),
+            (KeyEventKind::Repeat, b'A'),
+            (KeyEventKind::LongPress, b'A'),
+            (KeyEventKind::Released, b'A'),
+            (KeyEventKind::Pressed, b'+'),
+            (KeyEventKind::Pressed, b'x'),
+            (KeyEventKind::Released, b'#'),
+        ] {
+            uinput.event(&event(kind, chr)).unwrap();
+        }
+
+        assert_eq!(
+            uinput.writer().0,
+            [
+                key(30, 1),
+                key(30, 2),
+                key(30, 0),
+                key(78, 1),
+                key(0x20b, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_keycodes() {
+        let keycodes = Keycodes::parse("# code symbol\n\n  30 A\n0x20b #\n").unwrap();
+        assert_eq!(keycodes.get(Symbol::new(b'A')), Some(30));
+        assert_eq!(keycodes.get(Symbol::new(b'#')), Some(0x20b));
+        assert_eq!(keycodes.get(Symbol::new(b'B')), None);
+    }
+
+    #[test]
+    fn parse_keycodes_errors() {
+        let error = |text: &str| Keycodes::parse(text).unwrap_err().to_string();
+
+        assert_eq!(error("30 A\n31 A"), "line 2: symbol `A` assigned twice");
+        assert_eq!(error("0x300 A"), "line 1: invalid key code `0x300`");
+        assert_eq!(error("-1 A"), "line 1: invalid key code `-1`");
+        assert_eq!(error("A 30"), "line 1: invalid key code `A`");
+        assert_eq!(error("30"), "line 1: missing symbol");
+        assert_eq!(
+            error("30 AB"),
+            "line 1: symbol must be a single printable ASCII character"
+        );
+        assert_eq!(error("30 A B"), "line 1: unexpected `B`");
+    }
+}
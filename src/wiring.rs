@@ -0,0 +1,209 @@
+use anyhow::{Error, bail};
+use std::{fs, path::Path};
+
+use super::parse::{self, Fields};
+
+/// Connection of the key matrix to the chip.
+///
+/// Port B lines drive the rows, port A lines sense the columns. Each of the
+/// eight port B lines drives one `(pad, row)`; three port A lines sense the
+/// columns shared by both pads.
+///
+/// Text representation has one line per pin: `row <bit> <pad> <row>` for a
+/// port B line or `column <bit> <column>` for a port A line, all numbers
+/// counted from 0. Empty lines and lines starting with `#` are ignored.
+///
+/// ```text
+/// # port B bit 0 drives pad 0 row 0
+/// row 0 0 0
+/// # port A bit 4 senses column 0
+/// column 4 0
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wiring {
+    /// Matrix position `(pad, row)` driven by each port B line.
+    rows: [(usize, usize); 8],
+    /// Port A bit and matrix column of each sense line.
+    columns: [(u8, usize); 3],
+}
+
+impl Default for Wiring {
+    /// Wiring of the original board.
+    fn default() -> Self {
+        Self {
+            rows: [
+                (0, 0),
+                (0, 3),
+                (0, 2),
+                (1, 1),
+                (1, 0),
+                (1, 3),
+                (1, 2),
+                (0, 1),
+            ],
+            columns: [(1, 1), (4, 0), (7, 2)],
+        }
+    }
+}
+
+impl Wiring {
+    /// Load wiring from a file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text)
+    }
+
+    /// Parse wiring from its text representation.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut rows: [Option<(usize, usize)>; 8] = Default::default();
+        let mut columns: [Option<u8>; 3] = Default::default();
+
+        for (n, line) in parse::lines(text) {
+            let mut fields = Fields::new(n, line);
+            let kind = fields.next().unwrap_or_default();
+            match kind {
+                "row" => {
+                    let bit = fields.index("bit", 8)?;
+                    let position = (fields.index("pad", 2)?, fields.index("row", 4)?);
+                    if rows[bit].is_some() {
+                        bail!("line {n}: port B bit {bit} used twice");
+                    }
+                    if rows.contains(&Some(position)) {
+                        bail!(
+                            "line {n}: pad {} row {} driven twice",
+                            position.0,
+                            position.1
+                        );
+                    }
+                    rows[bit] = Some(position);
+                }
+                "column" => {
+                    let bit = fields.index("bit", 8)? as u8;
+                    let column = fields.index("column", 3)?;
+                    if columns.contains(&Some(bit)) {
+                        bail!("line {n}: port A bit {bit} used twice");
+                    }
+                    if columns[column].is_some() {
+                        bail!("line {n}: column {column} sensed twice");
+                    }
+                    columns[column] = Some(bit);
+                }
+                _ => bail!("line {n}: expected `row` or `column`, found `{kind}`"),
+            }
+            fields.end()?;
+        }
+
+        let mut wiring = Self::default();
+        for (bit, position) in rows.iter().enumerate() {
+            match position {
+                Some(position) => wiring.rows[bit] = *position,
+                None => bail!("port B bit {bit} is not connected"),
+            }
+        }
+        for (column, bit) in columns.iter().enumerate() {
+            match bit {
+                Some(bit) => wiring.columns[column] = (*bit, column),
+                None => bail!("column {column} is not connected"),
+            }
+        }
+        Ok(wiring)
+    }
+
+    /// Matrix position `(pad, row)` driven by each port B line.
+    #[inline]
+    pub fn rows(&self) -> &[(usize, usize); 8] {
+        &self.rows
+    }
+
+    /// Port A bit and matrix column of each sense line.
+    #[inline]
+    pub fn columns(&self) -> &[(u8, usize); 3] {
+        &self.columns
+    }
+
+    /// Port A bits of all sense lines.
+    pub fn column_mask(&self) -> u8 {
+        self.columns.iter().fold(0, |m, (bit, _)| m | (1 << bit))
+    }
+
+    /// Get pressed state of each column from port A input.
+    pub fn decode(&self, byte: u8) -> [bool; 3] {
+        let mut pressed = [false; 3];
+        for &(bit, column) in &self.columns {
+            pressed[column] = byte & (1 << bit) == 0;
+        }
+        pressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text representation of the default wiring.
+    const DEFAULT: &str = "\
+# port B drives the rows
+row 0 0 0
+row 1 0 3
+row 2 0 2
+row 3 1 1
+row 4 1 0
+row 5 1 3
+row 6 1 2
+row 7 0 1
+
+# port A senses the columns
+column 1 1
+column 4 0
+column 7 2
+";
+
+    #[test]
+    fn parse_wiring() {
+        let wiring = Wiring::parse(DEFAULT).unwrap();
+        let default = Wiring::default();
+        assert_eq!(wiring.rows(), default.rows());
+        assert_eq!(wiring.column_mask(), 0b1001_0010);
+        assert!((0..=u8::MAX).all(|byte| wiring.decode(byte) == default.decode(byte)));
+        assert_eq!(wiring.decode(!0b1000_0000), [false, false, true]);
+
+        let reordered = DEFAULT.replace("column 1 1", "column 2 1");
+        let wiring = Wiring::parse(&reordered).unwrap();
+        assert_eq!(wiring.columns(), &[(4, 0), (2, 1), (7, 2)]);
+        assert_eq!(wiring.decode(!0b0000_0100), [false, true, false]);
+    }
+
+    #[test]
+    fn parse_wiring_errors() {
+        let error = |text: &str| Wiring::parse(text).unwrap_err().to_string();
+
+        assert_eq!(error(""), "port B bit 0 is not connected");
+        assert_eq!(
+            error(&DEFAULT.replace("column 7 2", "")),
+            "column 2 is not connected"
+        );
+        assert_eq!(
+            error(&DEFAULT.replace("row 7 0 1", "row 6 0 1")),
+            "line 9: port B bit 6 used twice"
+        );
+        assert_eq!(
+            error(&DEFAULT.replace("row 7 0 1", "row 7 0 0")),
+            "line 9: pad 0 row 0 driven twice"
+        );
+        assert_eq!(
+            error(&DEFAULT.replace("column 7 2", "column 4 2")),
+            "line 14: port A bit 4 used twice"
+        );
+        assert_eq!(
+            error(&DEFAULT.replace("column 7 2", "column 7 0")),
+            "line 14: column 0 sensed twice"
+        );
+        assert_eq!(error("row 8 0 0"), "line 1: invalid bit `8`");
+        assert_eq!(error("row 0 0"), "line 1: missing row");
+        assert_eq!(error("row 0 0 0 1"), "line 1: unexpected `1`");
+        assert_eq!(
+            error("pin 0"),
+            "line 1: expected `row` or `column`, found `pin`"
+        );
+    }
+}